@@ -7,25 +7,70 @@ impl Plugin for CharacterBodyPlugin {
     fn build(&self, app: &mut App) {
         app.register_type::<CharacterBody>()
             .register_type::<CharacterGroundSnap>()
-            .register_type::<ForceSlide>();
+            .register_type::<ForceSlide>()
+            .register_type::<SurfaceProperties>()
+            .register_type::<CharacterContacts>();
+
+        app.add_message::<CharacterImpact>();
 
         app.add_systems(
             FixedUpdate,
-            ((character_body_movement, character_body_snap).chain()).in_set(PhysicsSystems::Last),
+            ((
+                character_body_movement,
+                character_body_snap,
+                character_body_overlaps,
+            )
+                .chain())
+            .in_set(PhysicsSystems::Last),
         );
     }
 }
 
+/// Kept so levels authored before [`SurfaceProperties`] keep working
 #[derive(Debug, Clone, Copy, PartialEq, Reflect, Component)]
+#[require(SurfaceProperties = SurfaceProperties { force_slide: true, ..SurfaceProperties::DEFAULT })]
 #[reflect(Component)]
 pub struct ForceSlide;
 
+/// Material of a surface, meant to be put on level meshes through Skein
+#[derive(Debug, Clone, Copy, PartialEq, Reflect, Component)]
+#[reflect(Component, Default)]
+pub struct SurfaceProperties {
+    /// Multiplier to how fast a body can brake and turn on this surface, ice goes below 1.0
+    pub friction: f32,
+    /// Multiplier to how fast a body can speed up on this surface
+    pub acceleration: f32,
+    /// Velocity given to bodies standing on the surface, in the surface's local space
+    pub conveyor_velocity: Vec3,
+    /// Fraction of the impact speed given back when hitting the surface
+    pub bounce_restitution: f32,
+    /// Makes grounded players slide
+    pub force_slide: bool,
+}
+
+impl SurfaceProperties {
+    pub const DEFAULT: Self = Self {
+        friction: 1.0,
+        acceleration: 1.0,
+        conveyor_velocity: Vec3::ZERO,
+        bounce_restitution: 0.0,
+        force_slide: false,
+    };
+}
+
+impl Default for SurfaceProperties {
+    fn default() -> Self {
+        Self::DEFAULT
+    }
+}
+
 #[derive(Debug, Clone, Copy, PartialEq, Reflect, Component)]
 #[require(
     RigidBody::Kinematic,
     LinearVelocity,
     CustomPositionIntegration,
-    TransformInterpolation
+    TransformInterpolation,
+    CharacterContacts
 )]
 #[reflect(Component)]
 pub struct CharacterBody {
@@ -33,7 +78,77 @@ pub struct CharacterBody {
     pub up: Dir3,
     pub max_dot_variance: f32,
     pub last_normal: Dir3,
-    pub force_slide: bool,
+    /// Properties of the floor the body is standing on, conveyor velocity is in world space
+    pub surface: SurfaceProperties,
+}
+
+/// Sent when a body runs hard into a surface or bounces off it
+#[derive(Message, Clone, Copy, Debug)]
+pub struct CharacterImpact {
+    pub entity: Entity,
+    pub normal: Dir3,
+    /// Speed the body had into the surface
+    pub speed: f32,
+    pub bounced: bool,
+}
+
+/// Entities the body touched or overlapped during the last physics tick
+#[derive(Debug, Clone, Default, PartialEq, Reflect, Component)]
+#[reflect(Component)]
+pub struct CharacterContacts {
+    current: Vec<Entity>,
+    previous: Vec<Entity>,
+}
+
+impl CharacterContacts {
+    pub fn contains(&self, entity: Entity) -> bool {
+        self.current.contains(&entity)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = Entity> + '_ {
+        self.current.iter().copied()
+    }
+
+    /// Entities touched this tick that weren't touched the tick before
+    pub fn entered(&self) -> impl Iterator<Item = Entity> + '_ {
+        self.iter().filter(|entity| !self.previous.contains(entity))
+    }
+
+    /// Entities touched the tick before that aren't touched anymore
+    pub fn exited(&self) -> impl Iterator<Item = Entity> + '_ {
+        self.previous
+            .iter()
+            .copied()
+            .filter(|entity| !self.current.contains(entity))
+    }
+
+    fn start_tick(&mut self) {
+        std::mem::swap(&mut self.current, &mut self.previous);
+        self.current.clear();
+    }
+
+    fn push(&mut self, entity: Entity) {
+        if !self.current.contains(&entity) {
+            self.current.push(entity);
+        }
+    }
+}
+
+impl CharacterBody {
+    /// Speed along the up direction
+    pub fn vertical_speed(&self, velocity: Vec3) -> f32 {
+        velocity.dot(*self.up)
+    }
+
+    /// Velocity without the part along the up direction
+    pub fn horizontal(&self, velocity: Vec3) -> Vec3 {
+        velocity.reject_from_normalized(*self.up)
+    }
+
+    /// Velocity with the part along the up direction replaced by the speed
+    pub fn with_vertical_speed(&self, velocity: Vec3, speed: f32) -> Vec3 {
+        self.horizontal(velocity) + *self.up * speed
+    }
 }
 
 #[derive(Debug, Clone, Copy, PartialEq, Reflect, Component)]
@@ -50,25 +165,41 @@ fn character_body_movement(
         &Collider,
         &mut Transform,
         &mut LinearVelocity,
+        &mut CharacterContacts,
         Option<&CharacterGroundSnap>,
     )>,
-    force_slide: Query<&ForceSlide>,
+    surfaces: Query<(&SurfaceProperties, Option<&GlobalTransform>)>,
     finish_line: Query<&crate::WinCondition>,
     mut timer: ResMut<crate::RunTimer>,
+    mut impacts: MessageWriter<CharacterImpact>,
     time: Res<Time>,
 ) {
-    for (entity, mut body, collider, mut transform, mut velocity, snap) in bodies.into_iter() {
-        body.force_slide = false;
+    for (entity, mut body, collider, mut transform, mut velocity, mut contacts, snap) in
+        bodies.into_iter()
+    {
+        contacts.start_tick();
+
+        let conveyor_velocity = if body.grounded {
+            body.surface.conveyor_velocity
+        } else {
+            Vec3::ZERO
+        };
+        body.surface = SurfaceProperties::DEFAULT;
 
         if snap.is_none() {
             body.grounded = false;
         }
 
+        let mut floor = FloorSurface::default();
+        let mut bounce: Option<(Dir3, f32)> = None;
+        let mut impact: Option<(Dir3, f32)> = None;
+        let velocity_before_move = velocity.0;
+
         let move_result = sliding.move_and_slide(
             collider,
             transform.translation,
             transform.rotation,
-            velocity.0,
+            velocity.0 + conveyor_velocity,
             time.delta(),
             &MoveAndSlideConfig {
                 move_and_slide_iterations: 255,
@@ -77,13 +208,24 @@ fn character_body_movement(
             },
             &SpatialQueryFilter::from_excluded_entities([entity]),
             |result| {
-                if result.normal.dot(*body.up) > body.max_dot_variance {
+                contacts.push(result.entity);
+
+                let surface = world_surface(surfaces.get(result.entity).ok());
+
+                let impact_speed = -velocity_before_move.dot(*result.normal);
+                if impact.is_none_or(|(_, speed)| impact_speed > speed) {
+                    impact = Some((*result.normal, impact_speed));
+                }
+
+                let up_alignment = result.normal.dot(*body.up);
+                if up_alignment > body.max_dot_variance {
                     body.grounded = true;
                 }
+                floor.add(surface, up_alignment, body.max_dot_variance);
                 body.last_normal = *result.normal;
 
-                if force_slide.get(result.entity).is_ok() {
-                    body.force_slide = true;
+                if surface.bounce_restitution > 0.0 {
+                    bounce = Some((*result.normal, surface.bounce_restitution));
                 }
 
                 if finish_line.get(result.entity).is_ok() {
@@ -95,10 +237,86 @@ fn character_body_movement(
         );
 
         transform.translation = move_result.position;
-        velocity.0 = move_result.projected_velocity;
+        velocity.0 = move_result.projected_velocity - conveyor_velocity;
+        body.surface = floor.merged(SurfaceProperties::DEFAULT);
+
+        let mut bounced = false;
+        if let Some((normal, restitution)) = bounce {
+            let impact_speed = -velocity_before_move.dot(*normal);
+
+            if impact_speed > MIN_BOUNCE_SPEED {
+                velocity.0 += *normal * impact_speed * restitution;
+                bounced = true;
+
+                if normal.dot(*body.up) > body.max_dot_variance {
+                    body.grounded = false;
+                }
+            }
+        }
+
+        if let Some((normal, speed)) = impact
+            && (bounced || speed > MIN_IMPACT_SPEED)
+        {
+            impacts.write(CharacterImpact {
+                entity,
+                normal,
+                speed,
+                bounced,
+            });
+        }
     }
 }
 
+const MIN_BOUNCE_SPEED: f32 = 1.0;
+/// Speed into a surface a hit needs to be sent as an impact
+const MIN_IMPACT_SPEED: f32 = 5.0;
+
+/// Surface of the floor a body touched during one move, so the hit order doesn't matter. The floor
+/// facing up the most gives the properties, and a slide forced by any hit sticks
+#[derive(Default)]
+struct FloorSurface {
+    floor: Option<(f32, SurfaceProperties)>,
+    force_slide: bool,
+}
+
+impl FloorSurface {
+    fn add(&mut self, surface: SurfaceProperties, up_alignment: f32, max_dot_variance: f32) {
+        self.force_slide |= surface.force_slide;
+
+        if up_alignment > max_dot_variance && self.floor.is_none_or(|(best, _)| up_alignment > best)
+        {
+            self.floor = Some((up_alignment, surface));
+        }
+    }
+
+    fn touched_floor(&self) -> bool {
+        self.floor.is_some()
+    }
+
+    /// Properties of the floor, or of `fallback` if no floor was touched
+    fn merged(&self, fallback: SurfaceProperties) -> SurfaceProperties {
+        let mut surface = self.floor.map_or(fallback, |(_, surface)| surface);
+        surface.force_slide |= self.force_slide;
+        surface
+    }
+}
+
+/// Get the properties of a surface with the conveyor velocity moved to world space
+fn world_surface(
+    surface: Option<(&SurfaceProperties, Option<&GlobalTransform>)>,
+) -> SurfaceProperties {
+    let Some((surface, transform)) = surface else {
+        return SurfaceProperties::DEFAULT;
+    };
+
+    let mut surface = *surface;
+    if let Some(transform) = transform {
+        surface.conveyor_velocity = transform.rotation() * surface.conveyor_velocity;
+    }
+
+    surface
+}
+
 fn character_body_snap(
     sliding: MoveAndSlide,
     bodies: Query<(
@@ -108,10 +326,13 @@ fn character_body_snap(
         &mut Transform,
         &LinearVelocity,
         &CharacterGroundSnap,
+        &mut CharacterContacts,
     )>,
-    force_slide: Query<&ForceSlide>,
+    surfaces: Query<(&SurfaceProperties, Option<&GlobalTransform>)>,
 ) {
-    for (entity, mut body, collider, mut transform, velocity, snap) in bodies.into_iter() {
+    for (entity, mut body, collider, mut transform, velocity, snap, mut contacts) in
+        bodies.into_iter()
+    {
         // TODO: ADD VELOCITY DEPENDANT SNAPPING
         let _ = velocity;
 
@@ -119,7 +340,7 @@ fn character_body_snap(
             continue;
         }
 
-        let mut touched_floor = false;
+        let mut floor = FloorSurface::default();
         let snap_movement = sliding.move_and_slide(
             collider,
             transform.translation,
@@ -133,23 +354,85 @@ fn character_body_snap(
             },
             &SpatialQueryFilter::from_excluded_entities([entity]),
             |hit| {
-                if hit.normal.dot(*body.up) > body.max_dot_variance {
-                    touched_floor = true;
-                    //velocity.0 = (velocity.0.reject_from_normalized(hit.normal.as_vec3())).normalize_or_zero() * velocity.0.length();
-                }
+                contacts.push(hit.entity);
 
-                if force_slide.get(hit.entity).is_ok() {
-                    body.force_slide = true;
-                }
+                let surface = world_surface(surfaces.get(hit.entity).ok());
+
+                //velocity.0 = (velocity.0.reject_from_normalized(hit.normal.as_vec3())).normalize_or_zero() * velocity.0.length();
+                floor.add(surface, hit.normal.dot(*body.up), body.max_dot_variance);
 
                 body.last_normal = *hit.normal;
                 MoveAndSlideHitResponse::Accept
             },
         );
-        if touched_floor {
+        if floor.touched_floor() {
             transform.translation = snap_movement.position;
+            // A slide forced during the move still counts
+            let force_slide = body.surface.force_slide;
+            body.surface = floor.merged(SurfaceProperties::DEFAULT);
+            body.surface.force_slide |= force_slide;
         } else {
             body.grounded = false;
         }
     }
 }
+
+/// Add sensors and anything else the body is inside of to its contacts
+fn character_body_overlaps(
+    spatial_query: SpatialQuery,
+    bodies: Query<(Entity, &Collider, &Transform, &mut CharacterContacts), With<CharacterBody>>,
+) {
+    for (entity, collider, transform, mut contacts) in bodies {
+        let overlaps = spatial_query.shape_intersections(
+            collider,
+            transform.translation,
+            transform.rotation,
+            &SpatialQueryFilter::from_excluded_entities([entity]),
+        );
+
+        for overlap in overlaps {
+            contacts.push(overlap);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MAX_DOT_VARIANCE: f32 = 0.7;
+
+    fn merge(hits: &[(SurfaceProperties, f32)]) -> SurfaceProperties {
+        let mut floor = FloorSurface::default();
+        for (surface, up_alignment) in hits {
+            floor.add(*surface, *up_alignment, MAX_DOT_VARIANCE);
+        }
+        floor.merged(SurfaceProperties::DEFAULT)
+    }
+
+    #[test]
+    fn floor_surface_does_not_depend_on_hit_order() {
+        let ice = SurfaceProperties {
+            friction: 0.1,
+            ..SurfaceProperties::DEFAULT
+        };
+        let slide_ramp = SurfaceProperties {
+            force_slide: true,
+            ..SurfaceProperties::DEFAULT
+        };
+        let wall = SurfaceProperties {
+            bounce_restitution: 1.0,
+            ..SurfaceProperties::DEFAULT
+        };
+        let hits = [(ice, 1.0), (slide_ramp, 0.8), (wall, 0.0)];
+
+        let mut reversed = hits;
+        reversed.reverse();
+
+        let merged = merge(&hits);
+        assert_eq!(merged, merge(&reversed));
+        assert_eq!(merged.friction, ice.friction);
+        assert!(merged.force_slide);
+        assert_eq!(merged.bounce_restitution, 0.0);
+    }
+}
@@ -0,0 +1,89 @@
+use crate::character_body::{CharacterBody, CharacterContacts};
+
+use avian3d::prelude::*;
+use bevy::prelude::*;
+
+pub struct GravityPlugin;
+
+impl Plugin for GravityPlugin {
+    fn build(&self, app: &mut App) {
+        app.register_type::<GravityZone>();
+
+        app.add_systems(
+            FixedUpdate,
+            character_body_gravity_zones.before(PhysicsSystems::Last),
+        );
+    }
+}
+
+/// Volume that changes the up direction of the character bodies inside it
+#[derive(Debug, Clone, Copy, PartialEq, Reflect, Component)]
+#[require(Sensor, ColliderConstructor::ConvexHullFromMesh)]
+#[reflect(Component, Default)]
+pub struct GravityZone {
+    pub shape: GravityShape,
+    /// When zones overlap the one with the highest priority is used
+    pub priority: i32,
+}
+
+impl Default for GravityZone {
+    fn default() -> Self {
+        Self {
+            shape: GravityShape::Directional,
+            priority: 0,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Reflect)]
+pub enum GravityShape {
+    /// Pulls along the local -Y of the zone
+    Directional,
+    /// Pulls towards the origin of the zone, like a planet
+    Spherical,
+    /// Pulls towards the local Y axis of the zone
+    Cylindrical,
+}
+
+impl GravityShape {
+    /// Up direction at a point for a zone with the given transform
+    pub fn up_at(&self, zone: &GlobalTransform, point: Vec3) -> Option<Dir3> {
+        match self {
+            GravityShape::Directional => Some(zone.up()),
+            GravityShape::Spherical => Dir3::new(point - zone.translation()).ok(),
+            GravityShape::Cylindrical => {
+                Dir3::new((point - zone.translation()).reject_from_normalized(*zone.up())).ok()
+            }
+        }
+    }
+}
+
+/// Radians per second the up of a body turns towards the up of its zone
+const UP_TURN_RATE: f32 = 6.0;
+
+fn character_body_gravity_zones(
+    bodies: Query<(&mut CharacterBody, &CharacterContacts, &Transform)>,
+    zones: Query<(&GravityZone, &GlobalTransform)>,
+    time: Res<Time>,
+) {
+    for (mut body, contacts, transform) in bodies {
+        let zone = contacts
+            .iter()
+            .filter_map(|entity| zones.get(entity).ok())
+            .max_by_key(|(zone, _)| zone.priority);
+
+        let target_up = zone
+            .and_then(|(zone, zone_transform)| {
+                zone.shape.up_at(zone_transform, transform.translation)
+            })
+            .unwrap_or(Dir3::Y);
+
+        let angle = body.up.angle_between(*target_up);
+        if angle <= f32::EPSILON {
+            continue;
+        }
+
+        let turn = (UP_TURN_RATE * time.delta_secs() / angle).min(1.0);
+        body.up = body.up.slerp(target_up, turn);
+    }
+}
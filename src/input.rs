@@ -15,6 +15,12 @@ pub enum PlayerInput {
     Jump,
     Crouch,
     Attack,
+    Grapple,
+    Dash,
+    ToggleView,
+    Zoom,
+    ZoomHold,
+    ToggleShake,
 }
 
 impl Actionlike for PlayerInput {
@@ -25,6 +31,12 @@ impl Actionlike for PlayerInput {
             PlayerInput::Jump => InputControlKind::Button,
             PlayerInput::Crouch => InputControlKind::Button,
             PlayerInput::Attack => InputControlKind::Button,
+            PlayerInput::Grapple => InputControlKind::Button,
+            PlayerInput::Dash => InputControlKind::Button,
+            PlayerInput::ToggleView => InputControlKind::Button,
+            PlayerInput::Zoom => InputControlKind::Axis,
+            PlayerInput::ZoomHold => InputControlKind::Axis,
+            PlayerInput::ToggleShake => InputControlKind::Button,
         }
     }
 }
@@ -45,6 +57,12 @@ impl PlayerInput {
             )
             .with(PlayerInput::Jump, KeyCode::Space)
             .with(PlayerInput::Crouch, KeyCode::ControlLeft)
+            .with(PlayerInput::Attack, KeyCode::KeyE)
+            .with(PlayerInput::Grapple, MouseButton::Right)
+            .with(PlayerInput::Dash, KeyCode::ShiftLeft)
+            .with(PlayerInput::ToggleView, KeyCode::KeyV)
+            .with_axis(PlayerInput::Zoom, MouseScrollAxis::Y)
+            .with(PlayerInput::ToggleShake, KeyCode::KeyK)
             // Controller
             .with_dual_axis(
                 PlayerInput::Move,
@@ -60,5 +78,14 @@ impl PlayerInput {
             )
             .with(PlayerInput::Jump, GamepadButton::South)
             .with(PlayerInput::Crouch, GamepadButton::West)
+            .with(PlayerInput::Attack, GamepadButton::North)
+            .with(PlayerInput::Grapple, GamepadButton::RightTrigger2)
+            .with(PlayerInput::Dash, GamepadButton::East)
+            .with(PlayerInput::ToggleView, GamepadButton::RightThumb)
+            .with(PlayerInput::ToggleShake, GamepadButton::Select)
+            .with_axis(
+                PlayerInput::ZoomHold,
+                VirtualAxis::new(GamepadButton::DPadDown, GamepadButton::DPadUp),
+            )
     }
 }
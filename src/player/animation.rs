@@ -0,0 +1,695 @@
+use super::state_machine::StateMachine;
+use super::transitions::MoveStateKind;
+use crate::character_body::CharacterBody;
+use crate::input::PlayerInput;
+use crate::ron_asset::RonAssetPlugin;
+
+use avian3d::prelude::*;
+use bevy::animation::{AnimationTargetId, RepeatAnimation};
+use bevy::prelude::*;
+use leafwing_input_manager::prelude::*;
+use serde::Deserialize;
+
+use std::collections::{HashMap, HashSet};
+
+pub const MISERERE_PATH: &str = "miserere.glb";
+pub const PLAYER_ANIMATIONS_PATH: &str = "player.animations.ron";
+
+pub(super) struct PlayerAnimationPlugin;
+impl Plugin for PlayerAnimationPlugin {
+    fn build(&self, app: &mut App) {
+        app.register_type::<CharacterAnimator>();
+
+        app.add_plugins(RonAssetPlugin::<PlayerAnimations>::new(&["animations.ron"]));
+
+        app.add_systems(
+            Update,
+            (
+                spawn_character_models,
+                link_animation_players,
+                build_animation_layers,
+                animate_characters,
+            )
+                .chain(),
+        );
+    }
+}
+
+/// Which clips play in each movement state, loaded from `player.animations.ron`
+#[derive(Asset, TypePath, Deserialize, Clone, Debug)]
+pub struct PlayerAnimations {
+    pub states: HashMap<MoveStateKind, StateAnimation>,
+    /// Cross-fade duration used when no fade rule matches
+    pub default_fade: f32,
+    pub fades: Vec<FadeRule>,
+    /// Played on top of the state animations, only read once when the model loads
+    pub layers: Vec<AnimationLayer>,
+}
+
+#[derive(Deserialize, Clone, Debug)]
+pub enum StateAnimation {
+    Single {
+        clip: String,
+        looping: bool,
+    },
+    /// Intro clip played once, then a looping clip
+    Sequence {
+        intro: String,
+        looping: String,
+    },
+    /// Clips blended by how close a parameter is to each of their points
+    Blend1D {
+        parameter: BlendParameter,
+        points: Vec<(f32, String)>,
+    },
+}
+
+#[derive(Deserialize, Clone, Copy, Debug)]
+pub enum BlendParameter {
+    /// Speed of the player along the ground
+    HorizontalSpeed,
+    /// Speed of the player along its up direction
+    VerticalSpeed,
+}
+
+/// Cross-fade duration between two states, a missing state matches any state
+#[derive(Deserialize, Clone, Debug)]
+pub struct FadeRule {
+    pub from: Option<MoveStateKind>,
+    pub to: Option<MoveStateKind>,
+    pub duration: f32,
+}
+
+/// Clip played over part of the body on top of the state animations
+#[derive(Deserialize, Clone, Debug)]
+pub struct AnimationLayer {
+    pub clip: String,
+    /// The layer animates these bones and every bone under them, or the whole body if empty
+    pub bones: Vec<String>,
+    pub mode: LayerMode,
+    pub trigger: LayerTrigger,
+    /// Time to fade the layer in and out
+    pub fade: f32,
+}
+
+#[derive(Deserialize, Clone, Copy, Debug, PartialEq)]
+pub enum LayerMode {
+    /// Replaces the state animations on the layer's bones
+    Override,
+    /// Gets added to the state animations, the clip has to be made relative to the rest pose
+    Additive,
+}
+
+#[derive(Deserialize, Clone, Copy, Debug, PartialEq)]
+pub enum LayerTrigger {
+    /// Plays the clip once when attacking
+    Attack,
+}
+
+impl PlayerAnimations {
+    /// Duration of the most specific fade rule that matches the transition
+    pub fn fade_duration(&self, from: Option<MoveStateKind>, to: MoveStateKind) -> f32 {
+        let Some(from) = from else {
+            return 0.0;
+        };
+
+        self.fades
+            .iter()
+            .filter(|rule| rule.from.is_none_or(|kind| kind == from))
+            .filter(|rule| rule.to.is_none_or(|kind| kind == to))
+            .max_by_key(|rule| rule.from.is_some() as u8 + rule.to.is_some() as u8)
+            .map_or(self.default_fade, |rule| rule.duration)
+    }
+
+    /// Clips the states play, a model missing one of them can't be used. Layers are left out
+    /// since a layer without its clip only gets skipped
+    pub fn required_clips(&self) -> Vec<&str> {
+        self.states
+            .values()
+            .flat_map(|animation| match animation {
+                StateAnimation::Single { clip, looping: _ } => vec![clip.as_str()],
+                StateAnimation::Sequence { intro, looping } => {
+                    vec![intro.as_str(), looping.as_str()]
+                }
+                StateAnimation::Blend1D {
+                    parameter: _,
+                    points,
+                } => points.iter().map(|(_, clip)| clip.as_str()).collect(),
+            })
+            .collect()
+    }
+
+    /// Required clips the glTF doesn't have
+    pub fn missing_clips(&self, gltf: &Gltf) -> Vec<&str> {
+        self.required_clips()
+            .into_iter()
+            .filter(|clip| !gltf.named_animations.contains_key(*clip))
+            .collect()
+    }
+}
+
+/// Put on a child of a character to spawn a glTF scene under it, animated from the state of the character
+#[derive(Component)]
+pub struct CharacterModel {
+    gltf: Handle<Gltf>,
+    animations: Handle<PlayerAnimations>,
+    /// Built once the glTF is loaded
+    graph: Option<Handle<AnimationGraph>>,
+    nodes: HashMap<String, AnimationNodeIndex>,
+    /// Adds the additive layers on top of its first child, the locomotion node
+    layers_root: Option<AnimationNodeIndex>,
+    /// Blends the state animations with the override layers
+    locomotion: Option<AnimationNodeIndex>,
+    /// Clip node of every layer, in the same order as the layers
+    layer_nodes: Option<Vec<Option<AnimationNodeIndex>>>,
+}
+
+impl CharacterModel {
+    pub fn new(gltf: Handle<Gltf>, animations: Handle<PlayerAnimations>) -> Self {
+        Self {
+            gltf,
+            animations,
+            graph: None,
+            nodes: HashMap::new(),
+            layers_root: None,
+            locomotion: None,
+            layer_nodes: None,
+        }
+    }
+
+    pub fn gltf(&self) -> &Handle<Gltf> {
+        &self.gltf
+    }
+
+    fn is_layer_node(&self, node: AnimationNodeIndex) -> bool {
+        self.layer_nodes
+            .iter()
+            .flatten()
+            .any(|layer_node| *layer_node == Some(node))
+    }
+}
+
+/// Path of an asset for warnings
+fn source<A: Asset>(handle: &Handle<A>) -> String {
+    handle
+        .path()
+        .map_or_else(|| format!("{:?}", handle.id()), ToString::to_string)
+}
+
+/// Put on the entity holding the `AnimationPlayer` of a character model
+#[derive(Component, Reflect)]
+pub struct CharacterAnimator {
+    /// Entity whose state picks the animations
+    pub character: Entity,
+    /// Entity with the `CharacterModel` the animation player was spawned under
+    pub model: Entity,
+}
+
+/// Cross-fades the animations of the state the connected character is in
+#[derive(Component, Default)]
+pub struct AnimationController {
+    state: Option<MoveStateKind>,
+    /// Weights of the animations playing when the state was entered
+    previous_weights: HashMap<AnimationNodeIndex, f32>,
+    fade_duration: f32,
+    /// Goes from 0 to 1 over the fade duration
+    fade_progress: f32,
+    /// Whether a sequence moved on to its looping clip
+    intro_finished: bool,
+    /// Weight and whether it is playing of every layer
+    layers: Vec<LayerPlayback>,
+    /// Clips and states already warned about so the warning isn't spammed
+    missing_clips: HashSet<String>,
+}
+
+#[derive(Clone, Copy, Default)]
+struct LayerPlayback {
+    active: bool,
+    weight: f32,
+}
+
+/// Spawn the scene and build the animation graph of every model once its glTF and clips are loaded
+fn spawn_character_models(
+    mut commands: Commands,
+    models: Query<(Entity, &mut CharacterModel)>,
+    gltfs: Res<Assets<Gltf>>,
+    mappings: Res<Assets<PlayerAnimations>>,
+    mut graphs: ResMut<Assets<AnimationGraph>>,
+) {
+    for (entity, mut model) in models {
+        if model.graph.is_some() {
+            continue;
+        }
+        let (Some(gltf), Some(mapping)) = (gltfs.get(&model.gltf), mappings.get(&model.animations))
+        else {
+            continue;
+        };
+
+        // Skins are checked before they get swapped in, but the first model is spawned as is
+        let missing = mapping.missing_clips(gltf);
+        if !missing.is_empty() {
+            warn!(
+                "{} is missing the clips {missing:?} used by {}",
+                source(&model.gltf),
+                source(&model.animations)
+            );
+        }
+
+        // Additive layers only add on top of the first child of the add node
+        let mut graph = AnimationGraph::new();
+        let layers_root = graph.add_additive_blend(1.0, graph.root);
+        let locomotion = graph.add_blend(1.0, layers_root);
+
+        for (name, animation) in &gltf.named_animations {
+            let animation_node = graph.add_clip(animation.clone(), 1.0, locomotion);
+            model.nodes.insert(name.to_string(), animation_node);
+        }
+
+        model.layers_root = Some(layers_root);
+        model.locomotion = Some(locomotion);
+        model.graph = Some(graphs.add(graph));
+
+        match gltf.scenes.first() {
+            Some(scene) => {
+                commands.entity(entity).insert(SceneRoot(scene.clone()));
+            }
+            None => warn!("{} has no scenes", source(&model.gltf)),
+        }
+    }
+}
+
+/// Connect the animation players spawned with a model's scene to that model and its character
+fn link_animation_players(
+    mut commands: Commands,
+    players: Query<Entity, Added<AnimationPlayer>>,
+    parents: Query<&ChildOf>,
+    models: Query<&CharacterModel>,
+) {
+    for player in players {
+        let Some(model) = parents
+            .iter_ancestors(player)
+            .find(|ancestor| models.contains(*ancestor))
+        else {
+            continue;
+        };
+        let Some(graph) = models.get(model).ok().and_then(|model| model.graph.clone()) else {
+            continue;
+        };
+        let character = parents.get(model).map_or(model, ChildOf::parent);
+
+        commands.entity(player).insert((
+            CharacterAnimator { character, model },
+            AnimationGraphHandle(graph),
+            AnimationController::default(),
+        ));
+    }
+}
+
+/// Mask the layers to their bones and add their clips to the graph once the model is spawned
+fn build_animation_layers(
+    models: Query<(Entity, &mut CharacterModel)>,
+    mappings: Res<Assets<PlayerAnimations>>,
+    gltfs: Res<Assets<Gltf>>,
+    mut graphs: ResMut<Assets<AnimationGraph>>,
+    animators: Query<(Entity, &CharacterAnimator)>,
+    children: Query<&Children>,
+    bones: Query<(&Name, Option<&AnimationTargetId>)>,
+) {
+    for (entity, mut model) in models {
+        if model.layer_nodes.is_some() {
+            continue;
+        }
+
+        let (Some(layers_root), Some(locomotion), Some(graph)) =
+            (model.layers_root, model.locomotion, model.graph.clone())
+        else {
+            continue;
+        };
+        let Some(mapping) = mappings.get(&model.animations) else {
+            continue;
+        };
+        let Some(gltf) = gltfs.get(&model.gltf) else {
+            continue;
+        };
+        // Bones are only known once the scene is spawned under the animation player
+        let Some((root, _)) = animators
+            .iter()
+            .find(|(_, animator)| animator.model == entity)
+        else {
+            continue;
+        };
+        let Some(graph) = graphs.get_mut(&graph) else {
+            continue;
+        };
+
+        let mut layer_nodes = Vec::new();
+        for (group, layer) in mapping.layers.iter().enumerate() {
+            let group = group as u32;
+
+            if !layer.bones.is_empty() {
+                let layer_bones: HashSet<Entity> = children
+                    .iter_descendants(root)
+                    .filter(|entity| {
+                        bones.get(*entity).is_ok_and(|(name, _)| {
+                            layer.bones.iter().any(|bone| bone == name.as_str())
+                        })
+                    })
+                    .flat_map(|bone| std::iter::once(bone).chain(children.iter_descendants(bone)))
+                    .collect();
+
+                // The layer masks out its group, so every bone outside the layer goes in it
+                for entity in children.iter_descendants(root) {
+                    if let Ok((_, Some(target))) = bones.get(entity)
+                        && !layer_bones.contains(&entity)
+                    {
+                        graph.add_target_to_mask_group(*target, group);
+                    }
+                }
+            }
+
+            let Some(clip) = gltf.named_animations.get(layer.clip.as_str()) else {
+                warn!(
+                    "Animation clip \"{}\" is missing from {}",
+                    layer.clip,
+                    source(&model.gltf)
+                );
+                layer_nodes.push(None);
+                continue;
+            };
+
+            let parent = match layer.mode {
+                LayerMode::Override => locomotion,
+                LayerMode::Additive => layers_root,
+            };
+            layer_nodes.push(Some(graph.add_clip_with_mask(
+                clip.clone(),
+                1 << group,
+                1.0,
+                parent,
+            )));
+        }
+
+        model.layer_nodes = Some(layer_nodes);
+    }
+}
+
+impl AnimationController {
+    fn enter(
+        &mut self,
+        state: MoveStateKind,
+        fade_duration: f32,
+        player: &AnimationPlayer,
+        model: &CharacterModel,
+    ) {
+        self.state = Some(state);
+        self.intro_finished = false;
+        self.start_fade(fade_duration, player, model);
+    }
+
+    /// Fade from whatever is playing right now to the targets of the state
+    fn start_fade(&mut self, fade_duration: f32, player: &AnimationPlayer, model: &CharacterModel) {
+        self.previous_weights = player
+            .playing_animations()
+            .filter(|(node, _)| !model.is_layer_node(**node))
+            .map(|(node, animation)| (*node, animation.weight()))
+            .collect();
+        self.fade_duration = fade_duration;
+        self.fade_progress = 0.0;
+    }
+
+    /// Check if the intro of a sequence just finished so the loop can fade in
+    fn intro_just_finished(
+        &mut self,
+        animation: &StateAnimation,
+        model: &CharacterModel,
+        player: &AnimationPlayer,
+    ) -> bool {
+        let StateAnimation::Sequence { intro, looping: _ } = animation else {
+            return false;
+        };
+        if self.intro_finished {
+            return false;
+        }
+
+        self.intro_finished = match self.node(model, intro) {
+            Some(node) => player
+                .animation(node)
+                .is_some_and(|animation| animation.is_finished()),
+            None => true,
+        };
+        self.intro_finished
+    }
+
+    /// Get the node of a clip, warning once if the model doesn't have it
+    fn node(&mut self, model: &CharacterModel, clip: &str) -> Option<AnimationNodeIndex> {
+        let node = model.nodes.get(clip).copied();
+        if node.is_none() && self.missing_clips.insert(clip.to_string()) {
+            warn!(
+                "Animation clip \"{clip}\" is missing from {}",
+                source(&model.gltf)
+            );
+        }
+        node
+    }
+
+    /// Nodes the state wants to play with their weight and whether they loop
+    fn target_weights(
+        &mut self,
+        animation: &StateAnimation,
+        model: &CharacterModel,
+        parameter: impl Fn(BlendParameter) -> f32,
+    ) -> Vec<(AnimationNodeIndex, f32, bool)> {
+        match animation {
+            StateAnimation::Single { clip, looping } => self
+                .node(model, clip)
+                .map(|node| (node, 1.0, *looping))
+                .into_iter()
+                .collect(),
+            StateAnimation::Sequence { intro, looping } => {
+                if self.intro_finished {
+                    self.node(model, looping)
+                        .map(|node| (node, 1.0, true))
+                        .into_iter()
+                        .collect()
+                } else {
+                    self.node(model, intro)
+                        .map(|node| (node, 1.0, false))
+                        .into_iter()
+                        .collect()
+                }
+            }
+            StateAnimation::Blend1D {
+                parameter: blend_parameter,
+                points,
+            } => blend_weights(points, parameter(*blend_parameter))
+                .into_iter()
+                .filter_map(|(index, weight)| {
+                    self.node(model, &points[index].1)
+                        .map(|node| (node, weight, true))
+                })
+                .collect(),
+        }
+    }
+}
+
+/// Weights of the two points around the value, points have to be sorted
+fn blend_weights(points: &[(f32, String)], value: f32) -> Vec<(usize, f32)> {
+    let (Some(first), Some(last)) = (points.first(), points.last()) else {
+        return Vec::new();
+    };
+
+    if value <= first.0 {
+        return vec![(0, 1.0)];
+    }
+    if value >= last.0 {
+        return vec![(points.len() - 1, 1.0)];
+    }
+
+    for (index, window) in points.windows(2).enumerate() {
+        let (start, end) = (window[0].0, window[1].0);
+        if value <= end {
+            let t = (value - start) / (end - start).max(f32::EPSILON);
+            return vec![(index, 1.0 - t), (index + 1, t)];
+        }
+    }
+
+    Vec::new()
+}
+
+fn animate_characters(
+    models: Query<&CharacterModel>,
+    mappings: Res<Assets<PlayerAnimations>>,
+    characters: Query<(
+        &StateMachine,
+        &LinearVelocity,
+        &CharacterBody,
+        Option<&ActionState<PlayerInput>>,
+    )>,
+    animators: Query<(
+        &CharacterAnimator,
+        &mut AnimationPlayer,
+        &mut AnimationController,
+    )>,
+    time: Res<Time>,
+) {
+    for (animator, mut player, mut controller) in animators {
+        let Ok(model) = models.get(animator.model) else {
+            continue;
+        };
+        let Some(mapping) = mappings.get(&model.animations) else {
+            continue;
+        };
+        let Ok((state, velocity, body, input)) = characters.get(animator.character) else {
+            continue;
+        };
+
+        let kind = state.movement_state.kind();
+        let entered = controller.state != Some(kind);
+        if entered {
+            let fade_duration = mapping.fade_duration(controller.state, kind);
+            controller.enter(kind, fade_duration, &player, model);
+        }
+
+        // The intro of the previous visit could still read as finished on the frame it's entered
+        if !entered
+            && let Some(animation) = mapping.states.get(&kind)
+            && controller.intro_just_finished(animation, model, &player)
+        {
+            controller.start_fade(mapping.default_fade, &player, model);
+        }
+
+        controller.fade_progress = if controller.fade_duration > 0.0 {
+            (controller.fade_progress + time.delta_secs() / controller.fade_duration).min(1.0)
+        } else {
+            1.0
+        };
+        let progress = controller.fade_progress;
+
+        let targets = match mapping.states.get(&kind) {
+            Some(animation) => {
+                controller.target_weights(animation, model, |parameter| match parameter {
+                    BlendParameter::HorizontalSpeed => body.horizontal(velocity.0).length(),
+                    BlendParameter::VerticalSpeed => body.vertical_speed(velocity.0),
+                })
+            }
+            None => {
+                if controller.missing_clips.insert(format!("{kind:?}")) {
+                    warn!(
+                        "No animation for the {kind:?} state in {}",
+                        source(&model.animations)
+                    );
+                }
+                Vec::new()
+            }
+        };
+
+        let mut weights: HashMap<AnimationNodeIndex, f32> = controller
+            .previous_weights
+            .iter()
+            .map(|(node, weight)| (*node, weight * (1.0 - progress)))
+            .collect();
+
+        for (node, weight, looping) in targets {
+            if weight <= 0.0 {
+                continue;
+            }
+            *weights.entry(node).or_default() += weight * progress;
+
+            let animation = player.play(node);
+            animation.set_repeat(if looping {
+                RepeatAnimation::Forever
+            } else {
+                RepeatAnimation::Never
+            });
+            if entered && !looping {
+                animation.replay();
+            }
+        }
+
+        play_layers(
+            &mut controller,
+            &mut player,
+            &mut weights,
+            mapping,
+            model,
+            input,
+            time.delta_secs(),
+        );
+
+        let faded_out: Vec<AnimationNodeIndex> = player
+            .playing_animations()
+            .map(|(node, _)| *node)
+            .filter(|node| weights.get(node).is_none_or(|weight| *weight <= 0.0))
+            .collect();
+        for node in faded_out {
+            player.stop(node);
+        }
+
+        for (node, weight) in weights {
+            if let Some(animation) = player.animation_mut(node) {
+                animation.set_weight(weight);
+            }
+        }
+    }
+}
+
+/// Start, fade and stop the layers, adding the weights of their clips
+fn play_layers(
+    controller: &mut AnimationController,
+    player: &mut AnimationPlayer,
+    weights: &mut HashMap<AnimationNodeIndex, f32>,
+    mapping: &PlayerAnimations,
+    model: &CharacterModel,
+    input: Option<&ActionState<PlayerInput>>,
+    delta: f32,
+) {
+    let Some(layer_nodes) = &model.layer_nodes else {
+        return;
+    };
+    controller
+        .layers
+        .resize(layer_nodes.len(), LayerPlayback::default());
+
+    for ((layer, node), playback) in mapping
+        .layers
+        .iter()
+        .zip(layer_nodes)
+        .zip(&mut controller.layers)
+    {
+        let Some(node) = *node else {
+            continue;
+        };
+
+        let triggered = match layer.trigger {
+            LayerTrigger::Attack => {
+                input.is_some_and(|input| input.just_pressed(&PlayerInput::Attack))
+            }
+        };
+        if triggered {
+            playback.active = true;
+            player.start(node);
+        }
+
+        if player
+            .animation(node)
+            .is_some_and(|animation| animation.is_finished())
+        {
+            playback.active = false;
+        }
+
+        let target = if playback.active { 1.0 } else { 0.0 };
+        let step = if layer.fade > 0.0 {
+            delta / layer.fade
+        } else {
+            1.0
+        };
+        playback.weight += (target - playback.weight).clamp(-step, step);
+
+        // Override layers blend against the state animations which add up to 1
+        let weight = match layer.mode {
+            LayerMode::Override => playback.weight / (1.0 - playback.weight).max(0.01),
+            LayerMode::Additive => playback.weight,
+        };
+        weights.insert(node, weight);
+    }
+}
@@ -0,0 +1,187 @@
+use super::animation::{CharacterAnimator, CharacterModel};
+use super::state_machine::{MajorMoveState, MoveStateChanged};
+use crate::ron_asset::RonAssetPlugin;
+
+use bevy::prelude::*;
+use serde::Deserialize;
+
+use std::collections::{HashMap, HashSet};
+
+const ANIMATION_MARKERS_PATH: &str = "player.markers.ron";
+
+pub(super) struct AnimationEventsPlugin;
+impl Plugin for AnimationEventsPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_message::<Footstep>()
+            .add_message::<Landed>()
+            .add_message::<SlideLoopBegin>();
+
+        app.add_plugins(RonAssetPlugin::<AnimationMarkers>::new(&["markers.ron"]));
+
+        app.add_systems(Startup, load_animation_markers);
+        app.add_systems(Update, (add_markers_to_clips, detect_landing));
+
+        app.add_observer(send_marker_messages);
+    }
+}
+
+/// Sent when a foot of the player hits the ground in a clip
+#[derive(Message, Clone, Debug)]
+pub struct Footstep {
+    pub entity: Entity,
+    pub foot: Foot,
+}
+
+/// Sent when the player touches the ground after being in the air
+#[derive(Message, Clone, Debug)]
+pub struct Landed {
+    pub entity: Entity,
+    /// Airborne state the player landed from
+    pub from: MajorMoveState,
+}
+
+/// Sent when the intro of a slide is over and the slide loop starts
+#[derive(Message, Clone, Debug)]
+pub struct SlideLoopBegin {
+    pub entity: Entity,
+}
+
+/// Points in time of the player's clips, loaded from `player.markers.ron`
+#[derive(Asset, TypePath, Deserialize, Clone, Debug)]
+pub struct AnimationMarkers {
+    pub clips: HashMap<String, Vec<ClipMarker>>,
+    /// Weight a clip needs to have for its markers to fire, so faded out clips stay quiet
+    pub min_weight: f32,
+}
+
+#[derive(Deserialize, Clone, Debug)]
+pub struct ClipMarker {
+    pub time: MarkerTime,
+    pub marker: AnimationMarker,
+}
+
+#[derive(Deserialize, Clone, Copy, Debug)]
+pub enum MarkerTime {
+    /// Seconds from the start of the clip
+    Seconds(f32),
+    /// When the clip finishes or loops
+    End,
+}
+
+#[derive(Deserialize, Clone, Copy, Debug, PartialEq)]
+pub enum AnimationMarker {
+    Footstep(Foot),
+    SlideLoopBegin,
+}
+
+#[derive(Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Foot {
+    Left,
+    Right,
+}
+
+/// Triggered on the entity of the animation player when a clip reaches a marker
+#[derive(EntityEvent, Clone, Debug)]
+struct AnimationMarkerReached {
+    entity: Entity,
+    marker: AnimationMarker,
+}
+
+#[derive(Resource)]
+struct AnimationMarkersHandle(Handle<AnimationMarkers>);
+
+fn load_animation_markers(mut commands: Commands, asset_server: Res<AssetServer>) {
+    commands.insert_resource(AnimationMarkersHandle(
+        asset_server.load(ANIMATION_MARKERS_PATH),
+    ));
+}
+
+/// Add the markers to the clips of every model once both are loaded
+fn add_markers_to_clips(
+    mut added: Local<HashSet<AssetId<Gltf>>>,
+    models: Query<&CharacterModel>,
+    handle: Option<Res<AnimationMarkersHandle>>,
+    markers: Res<Assets<AnimationMarkers>>,
+    gltfs: Res<Assets<Gltf>>,
+    mut clips: ResMut<Assets<AnimationClip>>,
+) {
+    let Some(markers) = handle.and_then(|handle| markers.get(&handle.0)) else {
+        return;
+    };
+
+    for model in models {
+        // Models sharing a glTF share its clips too
+        if added.contains(&model.gltf().id()) {
+            continue;
+        }
+        let Some(gltf) = gltfs.get(model.gltf()) else {
+            continue;
+        };
+        added.insert(model.gltf().id());
+
+        add_markers(markers, gltf, &mut clips);
+    }
+}
+
+fn add_markers(markers: &AnimationMarkers, gltf: &Gltf, clips: &mut Assets<AnimationClip>) {
+    for (clip_name, clip_markers) in &markers.clips {
+        let Some(clip) = gltf
+            .named_animations
+            .get(clip_name.as_str())
+            .and_then(|clip| clips.get_mut(clip))
+        else {
+            warn!("Markers for missing animation clip \"{clip_name}\" in {ANIMATION_MARKERS_PATH}");
+            continue;
+        };
+
+        for clip_marker in clip_markers {
+            let time = match clip_marker.time {
+                MarkerTime::Seconds(seconds) => seconds,
+                MarkerTime::End => clip.duration(),
+            };
+            let marker = clip_marker.marker;
+            let min_weight = markers.min_weight;
+
+            clip.add_event_fn(time, move |commands, entity, _time, weight| {
+                if weight >= min_weight {
+                    commands.trigger(AnimationMarkerReached { entity, marker });
+                }
+            });
+        }
+    }
+}
+
+fn send_marker_messages(
+    trigger: On<AnimationMarkerReached>,
+    animators: Query<&CharacterAnimator>,
+    mut footsteps: MessageWriter<Footstep>,
+    mut slide_loops: MessageWriter<SlideLoopBegin>,
+) {
+    let Ok(animator) = animators.get(trigger.entity) else {
+        return;
+    };
+    let entity = animator.character;
+
+    match trigger.marker {
+        AnimationMarker::Footstep(foot) => {
+            footsteps.write(Footstep { entity, foot });
+        }
+        AnimationMarker::SlideLoopBegin => {
+            slide_loops.write(SlideLoopBegin { entity });
+        }
+    }
+}
+
+/// Landing has no clip of its own, so it comes from the state machine
+fn detect_landing(mut changes: MessageReader<MoveStateChanged>, mut landed: MessageWriter<Landed>) {
+    for change in changes.read() {
+        if matches!(change.from, MajorMoveState::Airborne(_))
+            && matches!(change.to, MajorMoveState::Grounded(_))
+        {
+            landed.write(Landed {
+                entity: change.entity,
+                from: change.from.clone(),
+            });
+        }
+    }
+}
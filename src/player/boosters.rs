@@ -0,0 +1,156 @@
+use super::state_machine::*;
+use crate::character_body::{CharacterBody, CharacterContacts};
+
+use avian3d::prelude::*;
+use bevy::prelude::*;
+
+pub(super) struct BoostersPlugin;
+impl Plugin for BoostersPlugin {
+    fn build(&self, app: &mut App) {
+        app.register_type::<LaunchPad>()
+            .register_type::<BoostRing>();
+
+        app.add_message::<Launched>();
+    }
+}
+
+/// Sent when a launch pad launches the player
+#[derive(Message, Clone, Copy, Debug)]
+pub struct Launched {
+    pub entity: Entity,
+    pub pad: Entity,
+}
+
+/// Launches the player when touched or entered
+#[derive(Component, Reflect, Clone, Debug)]
+#[reflect(Component, Default)]
+pub struct LaunchPad {
+    /// Launch velocity in the pad's local space
+    pub impulse: Vec3,
+    pub mode: LaunchMode,
+    /// State to put the player in after the launch, Falling if none
+    pub forced_state: Option<MajorMoveState>,
+    /// Give the dive back to the player
+    pub restore_dive: bool,
+}
+
+impl Default for LaunchPad {
+    fn default() -> Self {
+        Self {
+            impulse: Vec3::Y * 15.0,
+            mode: LaunchMode::Override,
+            forced_state: None,
+            restore_dive: true,
+        }
+    }
+}
+
+#[derive(Reflect, Clone, Copy, Default, Debug, PartialEq)]
+pub enum LaunchMode {
+    /// Replace the velocity of the player with the impulse
+    #[default]
+    Override,
+    /// Add the impulse to the velocity of the player
+    Additive,
+}
+
+/// Scales the horizontal speed of the player going through it along its local Z axis
+#[derive(Component, Reflect, Clone, Copy, Debug)]
+#[require(Sensor)]
+#[reflect(Component, Default)]
+pub struct BoostRing {
+    pub speed_multiplier: f32,
+    /// Minimum speed along the axis after going through the ring
+    pub min_speed: f32,
+    /// Maximum speed along the axis after going through the ring
+    pub max_speed: f32,
+}
+
+impl Default for BoostRing {
+    fn default() -> Self {
+        Self {
+            speed_multiplier: 1.5,
+            min_speed: 15.0,
+            max_speed: 40.0,
+        }
+    }
+}
+
+pub(super) fn player_launch_pads(
+    players: Query<(
+        Entity,
+        &mut LinearVelocity,
+        &mut StateMachine,
+        &mut CharacterBody,
+        &CharacterContacts,
+    )>,
+    pads: Query<(&LaunchPad, &GlobalTransform)>,
+    mut launches: MessageWriter<Launched>,
+) {
+    for (entity, mut velocity, mut state, mut body, contacts) in players {
+        for entered in contacts.entered() {
+            let Ok((pad, pad_transform)) = pads.get(entered) else {
+                continue;
+            };
+
+            let impulse = pad_transform.rotation() * pad.impulse;
+
+            match pad.mode {
+                LaunchMode::Override => velocity.0 = impulse,
+                LaunchMode::Additive => velocity.0 += impulse,
+            }
+
+            body.grounded = false;
+            launches.write(Launched {
+                entity,
+                pad: entered,
+            });
+
+            let falling = MajorMoveState::Airborne(MinorAirborneState::Falling);
+            if let Err(error) =
+                state.force_transition(pad.forced_state.clone().unwrap_or(falling.clone()))
+            {
+                // A pad set to a state the table doesn't allow still launches, as a plain fall
+                warn!("Launch pad {entered} can't force its state: {error}");
+                let _ = state.force_transition(falling);
+            }
+
+            if pad.restore_dive {
+                let dives = state
+                    .air_action_limit
+                    .unwrap_or(state.tuning.air_actions)
+                    .dives;
+                state.air_actions.dives = state.air_actions.dives.max(dives);
+            }
+        }
+    }
+}
+
+pub(super) fn player_boost_rings(
+    players: Query<(&mut LinearVelocity, &CharacterContacts, &CharacterBody), With<StateMachine>>,
+    rings: Query<(&BoostRing, &GlobalTransform)>,
+) {
+    for (mut velocity, contacts, body) in players {
+        for entered in contacts.entered() {
+            let Ok((ring, ring_transform)) = rings.get(entered) else {
+                continue;
+            };
+
+            let Some(axis) = body
+                .horizontal(ring_transform.rotation() * Vec3::Z)
+                .try_normalize()
+            else {
+                continue;
+            };
+
+            let along_axis = velocity.dot(axis);
+            let direction = if along_axis < 0.0 { -axis } else { axis };
+            let speed = along_axis.abs();
+
+            let boosted_speed =
+                (speed * ring.speed_multiplier).clamp(ring.min_speed, ring.max_speed);
+
+            velocity.0 += direction * (boosted_speed - speed);
+        }
+    }
+}
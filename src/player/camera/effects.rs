@@ -0,0 +1,211 @@
+use super::{CameraPivot, CameraSettings};
+use crate::character_body::{CharacterBody, CharacterImpact};
+use crate::player::animation_events::Landed;
+use crate::player::boosters::Launched;
+use crate::player::state_machine::{MajorMoveState, MinorAirborneState, StateMachine};
+
+use avian3d::prelude::*;
+use bevy::prelude::*;
+
+/// Sent to shake the camera, trauma adds up and the shake grows with its square
+#[derive(Message, Clone, Copy, Debug)]
+pub struct CameraShake {
+    pub trauma: f32,
+}
+
+/// Put on the camera to react to the speed and impacts of the player
+#[derive(Component, Reflect, Clone, Debug)]
+#[reflect(Component)]
+pub struct CameraEffects {
+    /// Horizontal speed the field of view starts widening at
+    pub fov_min_speed: f32,
+    /// Field of view in radians added for every unit of speed over the minimum
+    pub fov_per_speed: f32,
+    /// Most field of view in radians that can be added
+    pub max_fov_boost: f32,
+    /// Fall speed a landing needs to shake the camera
+    pub landing_min_speed: f32,
+    /// Trauma for every unit of fall speed over the minimum
+    pub landing_trauma_per_speed: f32,
+    /// Speed into a wall a hit needs to shake the camera
+    pub wall_min_speed: f32,
+    /// Trauma for every unit of speed into a wall over the minimum
+    pub wall_trauma_per_speed: f32,
+    /// Trauma of bouncing off a bouncy surface or getting launched by a pad
+    pub bounce_trauma: f32,
+    /// Trauma lost per second
+    pub trauma_decay: f32,
+    /// Distance the camera moves at full trauma
+    pub max_shake_offset: f32,
+    /// Angle in radians the camera turns at full trauma
+    pub max_shake_angle: f32,
+    /// Distance the camera pulls back while diving
+    pub dive_dolly: f32,
+    /// How fast the field of view and the dolly catch up to their targets
+    pub smoothing: f32,
+
+    base_fov: Option<f32>,
+    fov_boost: f32,
+    dolly: f32,
+    trauma: f32,
+    /// Fall speed of the player last frame, since landing already stopped the fall this frame
+    fall_speed: f32,
+}
+
+impl CameraEffects {
+    /// Extra distance the orbit camera wants behind the pivot
+    pub fn dolly(&self) -> f32 {
+        self.dolly
+    }
+}
+
+impl Default for CameraEffects {
+    fn default() -> Self {
+        Self {
+            fov_min_speed: 10.0,
+            fov_per_speed: 0.015,
+            max_fov_boost: 0.35,
+            landing_min_speed: 15.0,
+            landing_trauma_per_speed: 0.03,
+            wall_min_speed: 12.0,
+            wall_trauma_per_speed: 0.03,
+            bounce_trauma: 0.3,
+            trauma_decay: 1.5,
+            max_shake_offset: 0.3,
+            max_shake_angle: 0.05,
+            dive_dolly: 1.0,
+            smoothing: 5.0,
+            base_fov: None,
+            fov_boost: 0.0,
+            dolly: 0.0,
+            trauma: 0.0,
+            fall_speed: 0.0,
+        }
+    }
+}
+
+/// Turn hard landings, wall hits, bounces and launch pads into camera shake
+pub(super) fn shake_on_impacts(
+    mut landings: MessageReader<Landed>,
+    mut impacts: MessageReader<CharacterImpact>,
+    mut launches: MessageReader<Launched>,
+    mut shakes: MessageWriter<CameraShake>,
+    cameras: Query<(&mut CameraEffects, &ChildOf)>,
+    pivots: Query<&CameraPivot>,
+    players: Query<(&LinearVelocity, &CharacterBody)>,
+) {
+    let landings: Vec<Entity> = landings.read().map(|landed| landed.entity).collect();
+    let impacts: Vec<CharacterImpact> = impacts.read().copied().collect();
+    let launches: Vec<Entity> = launches.read().map(|launch| launch.entity).collect();
+
+    for (mut effects, parent) in cameras {
+        let Ok(pivot) = pivots.get(parent.parent()) else {
+            continue;
+        };
+
+        if landings.contains(&pivot.0) {
+            let over = effects.fall_speed - effects.landing_min_speed;
+            if over > 0.0 {
+                shakes.write(CameraShake {
+                    trauma: over * effects.landing_trauma_per_speed,
+                });
+            }
+        }
+
+        let Ok((velocity, body)) = players.get(pivot.0) else {
+            continue;
+        };
+
+        // Landing on the floor already shook the camera through the landing message
+        for impact in impacts.iter().filter(|impact| impact.entity == pivot.0) {
+            let floor = impact.normal.dot(*body.up) > body.max_dot_variance;
+            let trauma = if impact.bounced {
+                effects.bounce_trauma
+            } else if !floor {
+                (impact.speed - effects.wall_min_speed).max(0.0) * effects.wall_trauma_per_speed
+            } else {
+                0.0
+            };
+            if trauma > 0.0 {
+                shakes.write(CameraShake { trauma });
+            }
+        }
+
+        for _ in launches.iter().filter(|entity| **entity == pivot.0) {
+            shakes.write(CameraShake {
+                trauma: effects.bounce_trauma,
+            });
+        }
+
+        effects.fall_speed = (-body.vertical_speed(velocity.0)).max(0.0);
+    }
+}
+
+/// Widen the field of view with speed, ease the dive dolly in and out and shake on top of the camera pose
+pub(super) fn apply_camera_effects(
+    mut shakes: MessageReader<CameraShake>,
+    cameras: Query<(
+        &mut Transform,
+        &mut Projection,
+        &mut CameraEffects,
+        &ChildOf,
+    )>,
+    pivots: Query<&CameraPivot>,
+    players: Query<(&LinearVelocity, &CharacterBody, &StateMachine)>,
+    settings: Res<CameraSettings>,
+    time: Res<Time>,
+) {
+    let added_trauma: f32 = shakes.read().map(|shake| shake.trauma).sum();
+    let delta = time.delta_secs();
+
+    for (mut transform, mut projection, mut effects, parent) in cameras {
+        let Ok(pivot) = pivots.get(parent.parent()) else {
+            continue;
+        };
+        let Ok((velocity, body, state)) = players.get(pivot.0) else {
+            continue;
+        };
+        let smoothing = 1.0 - (-effects.smoothing * delta).exp();
+
+        let speed = body.horizontal(velocity.0).length();
+        let target_fov_boost = ((speed - effects.fov_min_speed).max(0.0) * effects.fov_per_speed)
+            .min(effects.max_fov_boost);
+        effects.fov_boost += (target_fov_boost - effects.fov_boost) * smoothing;
+
+        if let Projection::Perspective(perspective) = projection.as_mut() {
+            let base_fov = *effects.base_fov.get_or_insert(perspective.fov);
+            perspective.fov = base_fov + effects.fov_boost;
+        }
+
+        // Added to the distance by the camera collision so it can't push the camera into walls
+        let target_dolly = match state.movement_state {
+            MajorMoveState::Airborne(MinorAirborneState::Dive) => effects.dive_dolly,
+            _ => 0.0,
+        };
+        effects.dolly += (target_dolly - effects.dolly) * smoothing;
+
+        effects.trauma =
+            (effects.trauma + added_trauma - effects.trauma_decay * delta).clamp(0.0, 1.0);
+        if !settings.shake || effects.trauma <= 0.0 {
+            continue;
+        }
+
+        let shake = effects.trauma * effects.trauma;
+        let t = time.elapsed_secs();
+        let offset = Vec3::new(shake_noise(t, 0.0), shake_noise(t, 1.0), 0.0);
+        let rotation = Quat::from_euler(
+            EulerRot::XYZ,
+            shake_noise(t, 2.0) * effects.max_shake_angle * shake,
+            shake_noise(t, 3.0) * effects.max_shake_angle * shake,
+            shake_noise(t, 4.0) * effects.max_shake_angle * shake,
+        );
+        transform.translation += transform.rotation * offset * effects.max_shake_offset * shake;
+        transform.rotation *= rotation;
+    }
+}
+
+/// Smooth noise between -1 and 1, a different curve for every seed
+fn shake_noise(time: f32, seed: f32) -> f32 {
+    let time = time * 25.0 + seed * 17.0;
+    (time.sin() + (time * 2.3 + 1.7).sin() * 0.5) / 1.5
+}
@@ -0,0 +1,159 @@
+use super::{CameraPivot, CameraUp};
+use crate::character_body::CharacterBody;
+use crate::player::{PLAYER_HEIGHT, PlayerCharacterMarker, PlayerLookDirection};
+
+use avian3d::prelude::*;
+use bevy::prelude::*;
+
+const SPEED_CAMERA: f32 = 1.0;
+/// Space kept between a lowered pivot and the ground under it
+const GROUND_CLEARANCE: f32 = 0.2;
+
+/// Put on the camera pivot to frame the player like a platformer, looking ahead of them and
+/// only following jumps once they leave a band around where the camera is
+#[derive(Component, Reflect, Clone, Debug)]
+#[reflect(Component)]
+pub struct CameraFraming {
+    /// Seconds of horizontal movement the camera looks ahead by
+    pub look_ahead_time: f32,
+    pub max_look_ahead: f32,
+    /// How far the player can go above the camera in the air before it follows
+    pub dead_zone_above: f32,
+    /// How far the player can go below the camera in the air before it follows
+    pub dead_zone_below: f32,
+    /// How fast the camera recenters on the player once grounded
+    pub recenter_rate: f32,
+    /// Fall speed the camera starts looking down at
+    pub fall_look_down_speed: f32,
+    /// Distance looked down for every unit of fall speed over the minimum
+    pub look_down_per_fall_speed: f32,
+    pub max_fall_look_down: f32,
+    /// How far ahead of the player the ground is checked for a ledge
+    pub ledge_probe_distance: f32,
+    /// Drop past the ledge that makes the camera look down
+    pub ledge_depth: f32,
+    /// Distance looked down while standing at a ledge
+    pub ledge_look_down: f32,
+    /// How fast the look-ahead and the look-down catch up to their targets
+    pub smoothing: f32,
+
+    look_ahead: Vec3,
+    /// Height of the framed point relative to the player, only changed by the dead-zone
+    frame_offset: f32,
+    look_down: f32,
+}
+
+impl Default for CameraFraming {
+    fn default() -> Self {
+        Self {
+            look_ahead_time: 0.15,
+            max_look_ahead: 1.5,
+            dead_zone_above: 1.5,
+            dead_zone_below: 0.5,
+            recenter_rate: 5.0,
+            fall_look_down_speed: 12.0,
+            look_down_per_fall_speed: 0.1,
+            max_fall_look_down: 2.0,
+            ledge_probe_distance: 0.8,
+            ledge_depth: 3.0,
+            ledge_look_down: 0.5,
+            smoothing: 4.0,
+            look_ahead: Vec3::ZERO,
+            frame_offset: 0.0,
+            look_down: 0.0,
+        }
+    }
+}
+
+pub(super) fn move_camera(
+    query: Query<
+        (&mut Transform, &mut CameraFraming, &CameraUp, &CameraPivot),
+        (Without<PlayerCharacterMarker>, Without<Collider>),
+    >,
+    players: Query<
+        (
+            &Transform,
+            &LinearVelocity,
+            &CharacterBody,
+            &PlayerLookDirection,
+        ),
+        With<PlayerCharacterMarker>,
+    >,
+    time: Res<Time>,
+    spatial_query: SpatialQuery,
+) {
+    let delta = time.delta_secs();
+
+    for (mut transform, mut framing, camera_up, pivot) in query {
+        let Ok((player_transform, velocity, body, look_direction)) = players.get(pivot.0) else {
+            continue;
+        };
+        let up = *camera_up.0;
+        let top_of_player = player_transform.translation + up * PLAYER_HEIGHT / 4.0;
+        let filter = SpatialQueryFilter::from_excluded_entities([pivot.0]);
+        let smoothing = 1.0 - (-framing.smoothing * delta).exp();
+
+        // Look ahead where the player is going, further the faster they go, but not into walls
+        let flat_velocity = velocity.reject_from_normalized(up);
+        let mut target_look_ahead =
+            (flat_velocity * framing.look_ahead_time).clamp_length_max(framing.max_look_ahead);
+        if let Ok(direction) = Dir3::new(target_look_ahead)
+            && let Some(hit) = spatial_query.cast_ray(
+                top_of_player,
+                direction,
+                target_look_ahead.length(),
+                false,
+                &filter,
+            )
+        {
+            target_look_ahead = direction * hit.distance;
+        }
+        framing.look_ahead = framing.look_ahead.lerp(target_look_ahead, smoothing);
+
+        // The framed point stays put while the player jumps around inside the dead-zone,
+        // and comes back to the player once they land
+        if body.grounded {
+            framing.frame_offset *= (-framing.recenter_rate * delta).exp();
+        } else {
+            framing.frame_offset -= velocity.dot(up) * delta;
+        }
+        framing.frame_offset = framing
+            .frame_offset
+            .clamp(-framing.dead_zone_above, framing.dead_zone_below);
+
+        // Look down on long falls and at ledges, so the player sees where they'll land
+        let fall_speed = -velocity.dot(up);
+        let mut target_look_down = ((fall_speed - framing.fall_look_down_speed)
+            * framing.look_down_per_fall_speed)
+            .clamp(0.0, framing.max_fall_look_down);
+        if body.grounded {
+            let forward = Dir3::new(flat_velocity)
+                .or_else(|_| Dir3::new(look_direction.0.reject_from_normalized(up)));
+            if let (Ok(forward), Ok(down)) = (forward, Dir3::new(-up)) {
+                let probe = top_of_player + forward * framing.ledge_probe_distance;
+                let ground_ahead = spatial_query
+                    .cast_ray(probe, down, framing.ledge_depth, true, &filter)
+                    .is_some();
+                if !ground_ahead {
+                    target_look_down = target_look_down.max(framing.ledge_look_down);
+                }
+            }
+        }
+        framing.look_down += (target_look_down - framing.look_down) * smoothing;
+
+        // Don't lower the pivot into the ground, the camera collision casts from it
+        let mut look_down = framing.look_down;
+        if let Ok(down) = Dir3::new(-up)
+            && let Some(hit) = spatial_query.cast_ray(top_of_player, down, look_down, true, &filter)
+        {
+            look_down = (hit.distance - GROUND_CLEARANCE).max(0.0);
+        }
+
+        let target_point =
+            top_of_player + framing.look_ahead + up * (framing.frame_offset - look_down);
+
+        transform.translation = transform
+            .translation
+            .move_towards(target_point, delta * (velocity.length() + SPEED_CAMERA));
+    }
+}
@@ -0,0 +1,502 @@
+mod effects;
+mod framing;
+mod occlusion;
+mod settings;
+mod volumes;
+
+pub use effects::{CameraEffects, CameraShake};
+pub use framing::CameraFraming;
+pub use occlusion::{CameraFadeable, CameraIgnore};
+pub use settings::CameraSettings;
+pub use volumes::{CameraVolume, CameraVolumeMode};
+
+use super::swimming::WaterVolume;
+use super::{PlayerCharacterMarker, PlayerLookDirection};
+use crate::character_body::CharacterBody;
+use crate::input::PlayerInput;
+
+use avian3d::prelude::*;
+use bevy::app::{HierarchyPropagatePlugin, Propagate};
+use bevy::camera::visibility::RenderLayers;
+use bevy::prelude::*;
+use leafwing_input_manager::prelude::*;
+
+/// Layer the player model is on, hidden from the camera in first person
+pub const PLAYER_MODEL_LAYER: usize = 1;
+
+pub struct CameraPlugin;
+impl Plugin for CameraPlugin {
+    fn build(&self, app: &mut App) {
+        app.register_type::<CameraPivot>()
+            .register_type::<CameraUp>()
+            .register_type::<CameraRig>()
+            .register_type::<CameraView>()
+            .register_type::<CameraFraming>()
+            .register_type::<CameraVolume>()
+            .register_type::<CameraEffects>()
+            .register_type::<CameraSettings>()
+            .register_type::<CameraIgnore>()
+            .register_type::<CameraFadeable>();
+
+        app.insert_resource(CameraSettings::load())
+            .add_message::<CameraShake>();
+
+        app.add_plugins(HierarchyPropagatePlugin::<RenderLayers>::new(PostUpdate));
+
+        app.add_systems(
+            FixedUpdate,
+            (
+                align_camera_up,
+                (rotate_camera_manual, rotate_camera_auto),
+                framing::move_camera,
+                unstuck_camera,
+                (update_camera_direction,),
+            )
+                .chain(),
+        );
+
+        //app.add_systems(FixedUpdate, (move_camera, unstuck_camera).chain());
+
+        app.add_systems(
+            Update,
+            (
+                zoom_camera,
+                toggle_camera_shake,
+                toggle_first_person,
+                volumes::blend_camera_volumes,
+                apply_camera_pose,
+                effects::shake_on_impacts,
+                effects::apply_camera_effects,
+            )
+                .chain(),
+        );
+
+        app.add_systems(
+            Update,
+            (occlusion::fade_occluders, occlusion::apply_fade_to_meshes).chain(),
+        );
+
+        app.add_systems(Update, settings::save_camera_settings);
+
+        app.add_systems(Update, underwater_fog);
+
+        app.add_observer(light_player_model);
+    }
+}
+
+#[derive(Component, Reflect, Clone, Copy)]
+#[require(TransformInterpolation, CameraUp, CameraView, CameraFraming)]
+#[reflect(Component)]
+pub struct CameraPivot(pub Entity);
+
+/// Whether the camera is in first person, blending in and out over `FIRST_PERSON_BLEND_TIME`
+#[derive(Component, Reflect, Clone, Copy, Default, Debug)]
+#[reflect(Component)]
+pub struct CameraView {
+    pub first_person: bool,
+    /// Goes from 0 in third person to 1 in first person
+    pub first_person_weight: f32,
+}
+
+/// Up direction the camera yaws around and measures its pitch from
+#[derive(Component, Reflect, Clone, Copy)]
+#[reflect(Component)]
+pub struct CameraUp(pub Dir3);
+
+impl Default for CameraUp {
+    fn default() -> Self {
+        Self(Dir3::Y)
+    }
+}
+
+/// Where the camera sits relative to its pivot, the camera systems write to it and it gets
+/// applied to the camera's transform once a frame
+#[derive(Component, Reflect, Clone, Copy, Debug)]
+#[reflect(Component)]
+pub struct CameraRig {
+    /// Distance behind the pivot of the orbit camera
+    pub distance: f32,
+    /// Speed the distance springs back out at after a collision pulled it in
+    pub distance_velocity: f32,
+    /// Camera volume the camera is blending to or from
+    pub volume: Option<Entity>,
+    /// How far the camera is blended from the orbit camera to the volume pose
+    pub volume_weight: f32,
+    /// World space pose the camera volume wants
+    pub volume_pose: Transform,
+}
+
+impl Default for CameraRig {
+    fn default() -> Self {
+        Self {
+            distance: CAMERA_DISTANCE,
+            distance_velocity: 0.0,
+            volume: None,
+            volume_weight: 0.0,
+            volume_pose: Transform::IDENTITY,
+        }
+    }
+}
+
+const CAMERA_UP_ALIGN_SPEED: f32 = 5.0;
+fn align_camera_up(
+    query: Query<(&mut Transform, &mut CameraUp, &CameraPivot), Without<PlayerCharacterMarker>>,
+    players: Query<&CharacterBody, With<PlayerCharacterMarker>>,
+    time: Res<Time>,
+) {
+    for (mut transform, mut camera_up, pivot) in query {
+        let Ok(body) = players.get(pivot.0) else {
+            continue;
+        };
+
+        let new_up = camera_up.0.slerp(
+            body.up,
+            (time.delta_secs() * CAMERA_UP_ALIGN_SPEED).min(1.0),
+        );
+
+        transform.rotation =
+            (Quat::from_rotation_arc(*camera_up.0, *new_up) * transform.rotation).normalize();
+        camera_up.0 = new_up;
+    }
+}
+
+fn rotate_camera_manual(
+    query: Query<(&mut Transform, &CameraUp, &CameraPivot), Without<PlayerCharacterMarker>>,
+    mut players: Query<&ActionState<PlayerInput>, With<PlayerCharacterMarker>>,
+    time: Res<Time>,
+) {
+    for (mut transform, camera_up, pivot) in query {
+        let Ok(input) = players.get_mut(pivot.0) else {
+            continue;
+        };
+
+        let camera_movement = input.axis_pair(&PlayerInput::Camera) * time.delta_secs();
+
+        let pitch = (transform.rotation * Vec3::NEG_Z)
+            .dot(*camera_up.0)
+            .clamp(-1.0, 1.0)
+            .asin();
+
+        let new_pitch =
+            (pitch - camera_movement.y).clamp(-80.0_f32.to_radians(), 80.0_f32.to_radians());
+
+        let diff = new_pitch - pitch;
+
+        transform.rotate_local_x(diff);
+        transform.rotate_axis(camera_up.0, -camera_movement.x);
+    }
+}
+
+const CAMERA_ROTATION_SPEED: f32 = 3.0;
+fn rotate_camera_auto(
+    query: Query<
+        (&mut Transform, &CameraUp, &CameraPivot, &CameraView),
+        Without<PlayerCharacterMarker>,
+    >,
+    mut players: Query<(&ActionState<PlayerInput>, &LinearVelocity), With<PlayerCharacterMarker>>,
+    time: Res<Time>,
+) {
+    for (mut transform, camera_up, pivot, view) in query {
+        // Only the mouse turns the head
+        if view.first_person {
+            continue;
+        }
+
+        let Ok((input, velocity)) = players.get_mut(pivot.0) else {
+            continue;
+        };
+        let up = *camera_up.0;
+
+        let camera_input = input.axis_pair(&PlayerInput::Camera);
+
+        // If camera is being pushed by player don't try to sway it
+        if camera_input.length_squared() > 0.25 {
+            continue;
+        }
+
+        let flat_velocity = velocity.reject_from_normalized(up);
+
+        // Get direction to point into
+        let Some(flat_dir) = flat_velocity.try_normalize() else {
+            continue;
+        };
+
+        let point_direction = (transform.rotation * Vec3::NEG_Z)
+            .reject_from_normalized(up)
+            .normalize_or_zero();
+
+        let mut angle_diff = point_direction
+            .cross(flat_dir)
+            .dot(up)
+            .atan2(point_direction.dot(flat_dir));
+
+        let factor = (angle_diff / (std::f32::consts::PI)).abs();
+
+        if angle_diff.abs() >= (std::f32::consts::PI) - 50.0_f32.to_radians() {
+            angle_diff = 0.0;
+        }
+
+        transform.rotate_axis(
+            camera_up.0,
+            angle_diff * time.delta_secs() * CAMERA_ROTATION_SPEED * factor,
+        );
+    }
+}
+
+fn update_camera_direction(
+    cameras: Query<(&Transform, &ChildOf), With<CameraRig>>,
+    pivots: Query<(&Transform, &CameraPivot)>,
+    mut players: Query<&mut PlayerLookDirection, With<PlayerCharacterMarker>>,
+) {
+    for (camera_transform, parent) in cameras {
+        let Ok((transform, pivot)) = pivots.get(parent.0) else {
+            continue;
+        };
+        let Ok(mut direction) = players.get_mut(pivot.0) else {
+            continue;
+        };
+
+        // Follows the camera volumes too so movement stays relative to the screen
+        direction.0 = transform.rotation * camera_transform.rotation * Vec3::NEG_Z;
+    }
+}
+
+const CAMERA_DISTANCE: f32 = 5.0;
+const MIN_CAMERA_DISTANCE: f32 = 2.0;
+const MAX_CAMERA_DISTANCE: f32 = 12.0;
+/// Distance zoomed per notch of the mouse wheel
+const ZOOM_SPEED: f32 = 0.5;
+/// Distance zoomed per second while the d-pad is held
+const ZOOM_HOLD_SPEED: f32 = 5.0;
+/// Stiffness of the spring pulling the camera to its distance, it's critically damped so it
+/// doesn't overshoot, and still snaps in right away when something is in the way
+const CAMERA_DISTANCE_STIFFNESS: f32 = 40.0;
+/// Runs every frame so no wheel notch is lost or counted twice between fixed ticks
+fn zoom_camera(
+    pivots: Query<&CameraPivot>,
+    players: Query<&ActionState<PlayerInput>, With<PlayerCharacterMarker>>,
+    mut settings: ResMut<CameraSettings>,
+    time: Res<Time>,
+) {
+    for pivot in pivots {
+        let Ok(input) = players.get(pivot.0) else {
+            continue;
+        };
+
+        let zoom = input.value(&PlayerInput::Zoom) * ZOOM_SPEED
+            + input.value(&PlayerInput::ZoomHold) * ZOOM_HOLD_SPEED * time.delta_secs();
+        if zoom != 0.0 {
+            settings.distance =
+                (settings.distance - zoom).clamp(MIN_CAMERA_DISTANCE, MAX_CAMERA_DISTANCE);
+        }
+    }
+}
+
+/// Camera shake can be turned off for accessibility, the choice is saved with the other preferences
+fn toggle_camera_shake(
+    pivots: Query<&CameraPivot>,
+    players: Query<&ActionState<PlayerInput>, With<PlayerCharacterMarker>>,
+    mut settings: ResMut<CameraSettings>,
+) {
+    for pivot in pivots {
+        let Ok(input) = players.get(pivot.0) else {
+            continue;
+        };
+
+        if input.just_pressed(&PlayerInput::ToggleShake) {
+            settings.shake = !settings.shake;
+            info!("Camera shake {}", if settings.shake { "on" } else { "off" });
+        }
+    }
+}
+
+fn unstuck_camera(
+    pivots: Query<(&Transform, &CameraPivot, &CameraView)>,
+    cameras: Query<(&mut CameraRig, Option<&CameraEffects>, &ChildOf)>,
+    passable: Query<(), Or<(With<CameraIgnore>, With<CameraFadeable>)>>,
+    parents: Query<&ChildOf>,
+    settings: Res<CameraSettings>,
+    time: Res<Time>,
+    spatial_query: SpatialQuery,
+) {
+    for (mut rig, effects, parent) in cameras {
+        let Ok((pivot_transform, pivot, view)) = pivots.get(parent.0) else {
+            continue;
+        };
+        if view.first_person {
+            continue;
+        }
+
+        // The dive dolly is part of the distance so the collision keeps it out of walls too
+        let wanted_distance = settings.distance + effects.map_or(0.0, CameraEffects::dolly);
+
+        let cast = spatial_query.cast_shape_predicate(
+            &Collider::sphere(0.1),
+            pivot_transform.translation,
+            Quat::IDENTITY,
+            Dir3::new(pivot_transform.rotation * Vec3::Z).unwrap(),
+            &ShapeCastConfig {
+                max_distance: wanted_distance,
+                target_distance: 0.0,
+                compute_contact_on_penetration: true,
+                ignore_origin_penetration: true,
+            },
+            &SpatialQueryFilter::from_excluded_entities([pivot.0]),
+            &|entity| !occlusion::camera_passes_through(entity, &passable, &parents),
+        );
+
+        if let Some(cast) = cast
+            && cast.distance < rig.distance
+        {
+            rig.distance = cast.distance;
+            rig.distance_velocity = 0.0;
+            continue;
+        }
+
+        let target_distance = cast.map_or(wanted_distance, |cast| cast.distance);
+        let delta = time.delta_secs();
+        let acceleration = CAMERA_DISTANCE_STIFFNESS * (target_distance - rig.distance)
+            - 2.0 * CAMERA_DISTANCE_STIFFNESS.sqrt() * rig.distance_velocity;
+        rig.distance_velocity += acceleration * delta;
+        rig.distance += rig.distance_velocity * delta;
+    }
+}
+
+const FIRST_PERSON_BLEND_TIME: f32 = 0.3;
+/// Height of the eyes above the center of the player
+const FIRST_PERSON_HEIGHT: f32 = 0.35;
+fn toggle_first_person(
+    pivots: Query<(&mut CameraView, &CameraPivot, &Children)>,
+    players: Query<&ActionState<PlayerInput>, With<PlayerCharacterMarker>>,
+    mut commands: Commands,
+    cameras: Query<Option<&RenderLayers>, With<CameraRig>>,
+    time: Res<Time>,
+) {
+    for (mut view, pivot, children) in pivots {
+        let Ok(input) = players.get(pivot.0) else {
+            continue;
+        };
+
+        if input.just_pressed(&PlayerInput::ToggleView) {
+            view.first_person = !view.first_person;
+        }
+
+        let target = if view.first_person { 1.0 } else { 0.0 };
+        let step = time.delta_secs() / FIRST_PERSON_BLEND_TIME;
+        view.first_person_weight += (target - view.first_person_weight).clamp(-step, step);
+
+        // Hide the model once the camera is closer to the head than to the orbit
+        let layers = if view.first_person_weight > 0.5 {
+            RenderLayers::layer(0)
+        } else {
+            RenderLayers::from_layers(&[0, PLAYER_MODEL_LAYER])
+        };
+        for camera in children.iter() {
+            if cameras
+                .get(camera)
+                .is_ok_and(|current| current != Some(&layers))
+            {
+                commands.entity(camera).insert(layers.clone());
+            }
+        }
+    }
+}
+
+/// Lights have to see the player model's layer for it to keep casting shadows in first person
+fn light_player_model(
+    trigger: On<Add, (PointLight, SpotLight, DirectionalLight)>,
+    layers: Query<&RenderLayers>,
+    mut commands: Commands,
+) {
+    let light_layers = layers
+        .get(trigger.entity)
+        .cloned()
+        .unwrap_or_default()
+        .with(PLAYER_MODEL_LAYER);
+    commands.entity(trigger.entity).insert(light_layers);
+}
+
+/// Blend between the orbit camera, the camera volume and first person in the pivot's space
+fn apply_camera_pose(
+    cameras: Query<(&mut Transform, &CameraRig, &ChildOf)>,
+    pivots: Query<(&Transform, &CameraPivot, &CameraView, &CameraUp), Without<CameraRig>>,
+    players: Query<&Transform, (Without<CameraPivot>, Without<CameraRig>)>,
+) {
+    for (mut transform, rig, parent) in cameras {
+        let Ok((pivot, pivot_target, view, camera_up)) = pivots.get(parent.0) else {
+            continue;
+        };
+
+        let orbit = Transform::from_xyz(0.0, 0.0, rig.distance);
+
+        let to_pivot = pivot.rotation.inverse();
+        let volume = Transform {
+            translation: to_pivot * (rig.volume_pose.translation - pivot.translation),
+            rotation: to_pivot * rig.volume_pose.rotation,
+            ..orbit
+        };
+
+        let weight = smoothstep(rig.volume_weight);
+        let mut pose = Transform {
+            translation: orbit.translation.lerp(volume.translation, weight),
+            rotation: orbit.rotation.slerp(volume.rotation, weight),
+            ..orbit
+        };
+
+        // The pivot trails behind the player, so the head is found from the player itself
+        if view.first_person_weight > 0.0
+            && let Ok(player) = players.get(pivot_target.0)
+        {
+            let head = player.translation + *camera_up.0 * FIRST_PERSON_HEIGHT;
+            let weight = smoothstep(view.first_person_weight);
+            pose.translation = pose
+                .translation
+                .lerp(to_pivot * (head - pivot.translation), weight);
+            pose.rotation = pose.rotation.slerp(Quat::IDENTITY, weight);
+        }
+
+        *transform = pose;
+    }
+}
+
+fn smoothstep(x: f32) -> f32 {
+    x * x * (3.0 - 2.0 * x)
+}
+
+/// Fog the camera goes back to when leaving the water
+#[derive(Component, Clone)]
+struct SurfaceFog(DistanceFog);
+
+const UNDERWATER_FOG_DENSITY: f32 = 0.08;
+fn underwater_fog(
+    mut commands: Commands,
+    cameras: Query<(
+        Entity,
+        &mut DistanceFog,
+        &GlobalTransform,
+        Option<&SurfaceFog>,
+    )>,
+    waters: Query<&WaterVolume>,
+    spatial_query: SpatialQuery,
+) {
+    for (entity, mut fog, transform, surface_fog) in cameras {
+        let Some(surface_fog) = surface_fog else {
+            commands.entity(entity).insert(SurfaceFog(fog.clone()));
+            continue;
+        };
+
+        let water = spatial_query
+            .point_intersections(transform.translation(), &SpatialQueryFilter::default())
+            .into_iter()
+            .find_map(|entity| waters.get(entity).ok());
+
+        if let Some(water) = water {
+            fog.color = water.fog_color;
+            fog.falloff = FogFalloff::Exponential {
+                density: UNDERWATER_FOG_DENSITY,
+            };
+        } else {
+            *fog = surface_fog.0.clone();
+        }
+    }
+}
@@ -0,0 +1,172 @@
+use super::{CameraPivot, CameraRig};
+
+use avian3d::prelude::*;
+use bevy::prelude::*;
+
+use std::collections::HashSet;
+
+/// Most colliders between the pivot and the camera checked for fading
+const MAX_OCCLUDERS: u32 = 8;
+
+/// The camera passes through this and its children without pulling in, authored through Skein
+#[derive(Component, Reflect, Clone, Copy, Default, Debug)]
+#[reflect(Component, Default)]
+pub struct CameraIgnore;
+
+/// The camera passes through this and its children and fades their meshes while they hide the
+/// player, for thin things like pillars that would make the camera jump in and out, authored
+/// through Skein
+#[derive(Component, Reflect, Clone, Copy, Debug)]
+#[reflect(Component, Default)]
+pub struct CameraFadeable {
+    /// Opacity of the meshes while they are in the way
+    pub opacity: f32,
+    /// Opacity gained or lost per second
+    pub fade_speed: f32,
+}
+
+impl Default for CameraFadeable {
+    fn default() -> Self {
+        Self {
+            opacity: 0.25,
+            fade_speed: 4.0,
+        }
+    }
+}
+
+/// How faded a `CameraFadeable` currently is, removed once it is fully visible again
+#[derive(Component, Clone, Copy, Debug)]
+struct CameraFade {
+    opacity: f32,
+}
+
+/// Material a faded mesh had before it got its own copy to fade
+#[derive(Component, Clone, Debug)]
+struct FadedMaterial {
+    original: Handle<StandardMaterial>,
+}
+
+/// Whether the camera collision skips an entity, either itself or a parent is ignored or fadeable
+pub(super) fn camera_passes_through(
+    entity: Entity,
+    passable: &Query<(), Or<(With<CameraIgnore>, With<CameraFadeable>)>>,
+    parents: &Query<&ChildOf>,
+) -> bool {
+    passable.contains(entity)
+        || parents
+            .iter_ancestors(entity)
+            .any(|ancestor| passable.contains(ancestor))
+}
+
+/// Fade the fadeable geometry between the pivot and the camera, and bring it back once it's clear
+pub(super) fn fade_occluders(
+    mut commands: Commands,
+    cameras: Query<(&CameraRig, &ChildOf)>,
+    pivots: Query<(&Transform, &CameraPivot)>,
+    fadeables: Query<(Entity, &CameraFadeable, Option<&mut CameraFade>)>,
+    parents: Query<&ChildOf>,
+    spatial_query: SpatialQuery,
+    time: Res<Time>,
+) {
+    let mut occluders = HashSet::new();
+    for (rig, parent) in cameras {
+        let Ok((pivot_transform, pivot)) = pivots.get(parent.0) else {
+            continue;
+        };
+        let Ok(direction) = Dir3::new(pivot_transform.rotation * Vec3::Z) else {
+            continue;
+        };
+
+        let hits = spatial_query.ray_hits(
+            pivot_transform.translation,
+            direction,
+            rig.distance,
+            MAX_OCCLUDERS,
+            true,
+            &SpatialQueryFilter::from_excluded_entities([pivot.0]),
+        );
+        for hit in hits {
+            let fadeable = std::iter::once(hit.entity)
+                .chain(parents.iter_ancestors(hit.entity))
+                .find(|entity| fadeables.contains(*entity));
+            occluders.extend(fadeable);
+        }
+    }
+
+    for (entity, fadeable, fade) in fadeables {
+        let target = if occluders.contains(&entity) {
+            fadeable.opacity
+        } else {
+            1.0
+        };
+
+        match fade {
+            Some(mut fade) => {
+                let step = fadeable.fade_speed * time.delta_secs();
+                fade.opacity += (target - fade.opacity).clamp(-step, step);
+                if fade.opacity >= 1.0 {
+                    commands.entity(entity).remove::<CameraFade>();
+                }
+            }
+            None if target < 1.0 => {
+                commands.entity(entity).insert(CameraFade { opacity: 1.0 });
+            }
+            None => {}
+        }
+    }
+}
+
+/// Give the meshes of faded geometry their own translucent material, and their old one back
+/// once they are visible again
+pub(super) fn apply_fade_to_meshes(
+    mut commands: Commands,
+    fades: Query<(Entity, Option<&CameraFade>), With<CameraFadeable>>,
+    children: Query<&Children>,
+    meshes: Query<(&MeshMaterial3d<StandardMaterial>, Option<&FadedMaterial>)>,
+    mut materials: ResMut<Assets<StandardMaterial>>,
+) {
+    for (entity, fade) in fades {
+        let opacity = fade.map_or(1.0, |fade| fade.opacity);
+
+        for mesh in std::iter::once(entity).chain(children.iter_descendants(entity)) {
+            let Ok((material, faded)) = meshes.get(mesh) else {
+                continue;
+            };
+
+            match (faded, opacity < 1.0) {
+                (Some(faded), true) => {
+                    let Some(original) = materials.get(&faded.original) else {
+                        continue;
+                    };
+                    let alpha = original.base_color.alpha() * opacity;
+                    if let Some(faded_material) = materials.get_mut(&material.0) {
+                        faded_material.base_color.set_alpha(alpha);
+                    }
+                }
+                (Some(faded), false) => {
+                    materials.remove(&material.0);
+                    commands
+                        .entity(mesh)
+                        .insert(MeshMaterial3d(faded.original.clone()))
+                        .remove::<FadedMaterial>();
+                }
+                (None, true) => {
+                    // The material is shared with every other instance of the mesh, so it gets copied
+                    let Some(mut faded_material) = materials.get(&material.0).cloned() else {
+                        continue;
+                    };
+                    faded_material.alpha_mode = AlphaMode::Blend;
+                    let alpha = faded_material.base_color.alpha() * opacity;
+                    faded_material.base_color.set_alpha(alpha);
+                    commands.entity(mesh).insert((
+                        MeshMaterial3d(materials.add(faded_material)),
+                        FadedMaterial {
+                            original: material.0.clone(),
+                        },
+                    ));
+                }
+                (None, false) => {}
+            }
+        }
+    }
+}
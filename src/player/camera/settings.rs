@@ -0,0 +1,81 @@
+use super::{CAMERA_DISTANCE, MAX_CAMERA_DISTANCE, MIN_CAMERA_DISTANCE};
+
+use bevy::prelude::*;
+use serde::{Deserialize, Serialize};
+
+/// Where the camera preferences are kept between runs, next to the game rather than in the assets
+const CAMERA_SETTINGS_PATH: &str = "camera.prefs.ron";
+/// Time without changes before the preferences are written, so zooming doesn't write every tick
+const SAVE_DELAY: f32 = 1.0;
+
+/// Options the player can change, saved to `camera.prefs.ron`
+#[derive(Resource, Reflect, Serialize, Deserialize, Clone, Debug)]
+#[reflect(Resource)]
+#[serde(default)]
+pub struct CameraSettings {
+    /// Camera shake, can be turned off for accessibility with K or select
+    pub shake: bool,
+    /// Distance of the orbit camera when nothing is in the way, changed by zooming
+    pub distance: f32,
+}
+
+impl Default for CameraSettings {
+    fn default() -> Self {
+        Self {
+            shake: true,
+            distance: CAMERA_DISTANCE,
+        }
+    }
+}
+
+impl CameraSettings {
+    /// Saved preferences, or the defaults if there are none yet
+    pub(super) fn load() -> Self {
+        let Ok(saved) = std::fs::read_to_string(CAMERA_SETTINGS_PATH) else {
+            return Self::default();
+        };
+
+        match ron::from_str::<Self>(&saved) {
+            Ok(mut settings) => {
+                settings.distance = settings
+                    .distance
+                    .clamp(MIN_CAMERA_DISTANCE, MAX_CAMERA_DISTANCE);
+                settings
+            }
+            Err(error) => {
+                warn!("Couldn't read {CAMERA_SETTINGS_PATH}, using the defaults: {error}");
+                Self::default()
+            }
+        }
+    }
+}
+
+pub(super) fn save_camera_settings(
+    settings: Res<CameraSettings>,
+    mut time_until_save: Local<Option<f32>>,
+    time: Res<Time>,
+) {
+    if settings.is_changed() && !settings.is_added() {
+        *time_until_save = Some(SAVE_DELAY);
+    }
+
+    let Some(remaining) = time_until_save.as_mut() else {
+        return;
+    };
+    *remaining -= time.delta_secs();
+    if *remaining > 0.0 {
+        return;
+    }
+    *time_until_save = None;
+
+    let saved = match ron::ser::to_string_pretty(&*settings, ron::ser::PrettyConfig::default()) {
+        Ok(saved) => saved,
+        Err(error) => {
+            warn!("Couldn't save the camera preferences: {error}");
+            return;
+        }
+    };
+    if let Err(error) = std::fs::write(CAMERA_SETTINGS_PATH, saved) {
+        warn!("Couldn't write {CAMERA_SETTINGS_PATH}: {error}");
+    }
+}
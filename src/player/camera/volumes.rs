@@ -0,0 +1,218 @@
+use super::{CameraPivot, CameraRig, CameraUp};
+use crate::character_body::CharacterContacts;
+use crate::player::PLAYER_HEIGHT;
+
+use avian3d::prelude::*;
+use bevy::prelude::*;
+
+/// Points sampled along a rail to find the closest one to the player
+const RAIL_SAMPLES: usize = 64;
+
+/// Takes the camera over while the player is inside it, blending back to the orbit camera on exit
+#[derive(Component, Reflect, Clone, Debug)]
+#[require(Sensor, ColliderConstructor::ConvexHullFromMesh)]
+#[reflect(Component, Default)]
+pub struct CameraVolume {
+    pub mode: CameraVolumeMode,
+    /// Time to blend in from the orbit camera and back out to it
+    pub blend_time: f32,
+    /// Wins over overlapping volumes with a lower priority
+    pub priority: i32,
+}
+
+impl Default for CameraVolume {
+    fn default() -> Self {
+        Self {
+            mode: CameraVolumeMode::Fixed {
+                position: Vec3::new(0.0, 5.0, 10.0),
+                look_at: Vec3::ZERO,
+                track_player: true,
+            },
+            blend_time: 0.5,
+            priority: 0,
+        }
+    }
+}
+
+/// Positions and directions are relative to the volume
+#[derive(Reflect, Clone, Debug)]
+pub enum CameraVolumeMode {
+    /// Camera stays at one point
+    Fixed {
+        position: Vec3,
+        look_at: Vec3,
+        /// Look at the player instead of `look_at`
+        track_player: bool,
+    },
+    /// Camera moves along a spline through the points to the spot closest to the player
+    Rail { points: Vec<Vec3> },
+    /// Camera looks along a locked direction from the side of the player
+    SideScroller {
+        direction: Vec3,
+        distance: f32,
+        height: f32,
+    },
+}
+
+impl CameraVolumeMode {
+    /// World space pose of the camera for a player at `target`
+    fn camera_pose(&self, volume: &GlobalTransform, target: Vec3, up: Vec3) -> Option<Transform> {
+        let (position, look_at) = match self {
+            CameraVolumeMode::Fixed {
+                position,
+                look_at,
+                track_player,
+            } => {
+                let look_at = if *track_player {
+                    target
+                } else {
+                    volume.transform_point(*look_at)
+                };
+                (volume.transform_point(*position), look_at)
+            }
+            CameraVolumeMode::Rail { points } => {
+                let curve = CubicCardinalSpline::new_catmull_rom(
+                    points.iter().map(|point| volume.transform_point(*point)),
+                )
+                .to_curve()
+                .ok()?;
+
+                let position = curve.iter_positions(RAIL_SAMPLES).min_by(|a, b| {
+                    a.distance_squared(target)
+                        .total_cmp(&b.distance_squared(target))
+                })?;
+                (position, target)
+            }
+            CameraVolumeMode::SideScroller {
+                direction,
+                distance,
+                height,
+            } => {
+                let direction = (volume.rotation() * *direction).try_normalize()?;
+                (target - direction * *distance + up * *height, target)
+            }
+        };
+
+        Some(Transform::from_translation(position).looking_at(look_at, up))
+    }
+}
+
+/// Pick the volume the player is in and blend the camera toward it, going through the orbit
+/// camera when moving from one volume to another
+pub(super) fn blend_camera_volumes(
+    cameras: Query<(&mut CameraRig, &ChildOf)>,
+    pivots: Query<(&CameraPivot, &CameraUp)>,
+    players: Query<(&Transform, &CharacterContacts)>,
+    volumes: Query<(&CameraVolume, &GlobalTransform)>,
+    time: Res<Time>,
+) {
+    for (mut rig, parent) in cameras {
+        let Ok((pivot, camera_up)) = pivots.get(parent.parent()) else {
+            continue;
+        };
+        let Ok((player_transform, contacts)) = players.get(pivot.0) else {
+            continue;
+        };
+
+        let entered = contacts
+            .iter()
+            .filter_map(|entity| Some((entity, volumes.get(entity).ok()?.0.priority)))
+            .max_by_key(|(_, priority)| *priority)
+            .map(|(entity, _)| entity);
+
+        let current = rig.volume.and_then(|entity| volumes.get(entity).ok());
+        let blend_time = current.map_or(0.0, |(volume, _)| volume.blend_time);
+        let step = if blend_time > 0.0 {
+            time.delta_secs() / blend_time
+        } else {
+            1.0
+        };
+
+        step_volume_blend(&mut rig, entered, step);
+
+        let Some((volume, volume_transform)) = current else {
+            continue;
+        };
+        let target = player_transform.translation + *camera_up.0 * PLAYER_HEIGHT / 4.0;
+        if let Some(pose) = volume
+            .mode
+            .camera_pose(volume_transform, target, *camera_up.0)
+        {
+            rig.volume_pose = pose;
+        }
+    }
+}
+
+/// Blend toward the volume the player entered, out of the current one first if it's another
+/// volume, and stay on the orbit camera while there is no volume
+fn step_volume_blend(rig: &mut CameraRig, entered: Option<Entity>, step: f32) {
+    if rig.volume.is_none() {
+        rig.volume = entered;
+        rig.volume_weight = 0.0;
+    } else if rig.volume == entered {
+        rig.volume_weight = (rig.volume_weight + step).min(1.0);
+    } else {
+        rig.volume_weight = (rig.volume_weight - step).max(0.0);
+        if rig.volume_weight <= 0.0 {
+            rig.volume = entered;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn volume() -> Entity {
+        Entity::from_raw_u32(1).unwrap()
+    }
+
+    #[test]
+    fn blends_into_an_entered_volume() {
+        let mut rig = CameraRig::default();
+
+        step_volume_blend(&mut rig, Some(volume()), 0.25);
+        assert_eq!(rig.volume, Some(volume()));
+        assert_eq!(rig.volume_weight, 0.0);
+
+        for _ in 0..3 {
+            step_volume_blend(&mut rig, Some(volume()), 0.25);
+        }
+        assert_eq!(rig.volume_weight, 0.75);
+        step_volume_blend(&mut rig, Some(volume()), 0.25);
+        step_volume_blend(&mut rig, Some(volume()), 0.25);
+        assert_eq!(rig.volume_weight, 1.0);
+    }
+
+    #[test]
+    fn blends_out_of_a_left_volume() {
+        let mut rig = CameraRig {
+            volume: Some(volume()),
+            volume_weight: 1.0,
+            ..default()
+        };
+
+        step_volume_blend(&mut rig, None, 0.5);
+        assert_eq!(rig.volume, Some(volume()));
+        assert_eq!(rig.volume_weight, 0.5);
+
+        step_volume_blend(&mut rig, None, 0.5);
+        assert_eq!(rig.volume, None);
+        assert_eq!(rig.volume_weight, 0.0);
+
+        step_volume_blend(&mut rig, None, 1.0);
+        assert_eq!(rig.volume, None);
+        assert_eq!(rig.volume_weight, 0.0);
+    }
+
+    #[test]
+    fn stays_on_the_orbit_camera_without_a_volume() {
+        let mut rig = CameraRig::default();
+
+        for _ in 0..10 {
+            step_volume_blend(&mut rig, None, 1.0);
+        }
+        assert_eq!(rig.volume, None);
+        assert_eq!(rig.volume_weight, 0.0);
+    }
+}
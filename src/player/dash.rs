@@ -0,0 +1,57 @@
+use super::PlayerLookDirection;
+use super::state_machine::*;
+use crate::character_body::CharacterBody;
+use crate::input::PlayerInput;
+
+use avian3d::prelude::*;
+use bevy::prelude::*;
+use leafwing_input_manager::prelude::*;
+
+/// Start a dash on press and keep the dash velocity until the state stops being stuck
+pub(super) fn player_dash(
+    players: Query<(
+        &mut LinearVelocity,
+        &mut StateMachine,
+        &ActionState<PlayerInput>,
+        &PlayerLookDirection,
+        &CharacterBody,
+    )>,
+) {
+    for (mut velocity, mut state, input, look_direction, body) in players {
+        if let MajorMoveState::Airborne(MinorAirborneState::Dash(direction)) = state.movement_state
+        {
+            if state.stuck_in_state_timer > 0.0 {
+                velocity.0 = direction * state.tuning.dash.speed();
+            } else {
+                velocity.0 = direction * state.tuning.dash.exit_speed;
+                let _ = state.transition(MajorMoveState::Airborne(MinorAirborneState::Falling));
+            }
+            continue;
+        }
+
+        if !input.just_pressed(&PlayerInput::Dash) {
+            continue;
+        }
+
+        let forward = body
+            .horizontal(look_direction.0)
+            .try_normalize()
+            .unwrap_or_else(|| body.up.any_orthonormal_vector());
+        let right = forward.cross(*body.up);
+
+        // Dash where the stick points, or where the camera looks without input
+        let input_axis = input.axis_pair(&PlayerInput::Move);
+        let direction = Dir3::new(forward * input_axis.y + right * input_axis.x)
+            .or_else(|_| Dir3::new(forward))
+            .unwrap_or(Dir3::NEG_Z);
+
+        if state
+            .transition(MajorMoveState::Airborne(MinorAirborneState::Dash(
+                direction,
+            )))
+            .is_ok()
+        {
+            velocity.0 = direction * state.tuning.dash.speed();
+        }
+    }
+}
@@ -0,0 +1,340 @@
+use super::animation::CharacterAnimator;
+use super::lean::ModelLean;
+use super::state_machine::{MajorMoveState, MinorGroundState, StateMachine};
+use crate::character_body::CharacterBody;
+
+use avian3d::prelude::*;
+use bevy::app::AnimationSystems;
+use bevy::prelude::*;
+use bevy::transform::TransformSystems;
+
+use std::collections::HashMap;
+
+pub(super) struct FootIkPlugin;
+impl Plugin for FootIkPlugin {
+    fn build(&self, app: &mut App) {
+        app.register_type::<GroundAlignment>();
+
+        // Runs on the animated pose, before it gets propagated to the meshes
+        app.add_systems(
+            PostUpdate,
+            align_model_to_ground
+                .after(AnimationSystems)
+                .before(TransformSystems::Propagate),
+        );
+    }
+}
+
+/// Put on the model child of the player to plant its feet and tilt it along the ground
+#[derive(Component, Reflect, Clone, Debug)]
+#[reflect(Component)]
+pub struct GroundAlignment {
+    pub foot_ik: bool,
+    /// Tilt the model toward the floor normal while sliding
+    pub slide_tilt: bool,
+    /// How far above a foot the ground is searched from
+    pub ray_up: f32,
+    /// How far under a foot the ground is searched to
+    pub ray_down: f32,
+    /// How far the hips can be lowered so the lower foot reaches the ground
+    pub max_pelvis_offset: f32,
+    /// How fast the hips and the tilt catch up to their targets
+    pub smoothing: f32,
+
+    #[reflect(ignore)]
+    rig: Option<LegRig>,
+    #[reflect(ignore)]
+    rest_translation: Option<Vec3>,
+    /// Pose the animation last wrote to the moved bones, not every clip animates every bone
+    #[reflect(ignore)]
+    animated_pose: HashMap<Entity, Transform>,
+    pelvis_offset: f32,
+    tilt: Quat,
+}
+
+impl Default for GroundAlignment {
+    fn default() -> Self {
+        Self {
+            foot_ik: true,
+            slide_tilt: true,
+            ray_up: 0.5,
+            ray_down: 0.5,
+            max_pelvis_offset: 0.3,
+            smoothing: 15.0,
+            rig: None,
+            rest_translation: None,
+            animated_pose: HashMap::new(),
+            pelvis_offset: 0.0,
+            tilt: Quat::IDENTITY,
+        }
+    }
+}
+
+/// Bones of the Miserere model moved by the alignment
+#[derive(Clone, Copy, Debug)]
+struct LegRig {
+    hips: Entity,
+    legs: [LegChain; 2],
+}
+
+#[derive(Clone, Copy, Debug)]
+struct LegChain {
+    upper: Entity,
+    lower: Entity,
+    paw: Entity,
+}
+
+impl LegRig {
+    fn bones(&self) -> impl Iterator<Item = Entity> + '_ {
+        std::iter::once(self.hips).chain(self.legs.iter().flat_map(|leg| [leg.upper, leg.lower]))
+    }
+
+    fn find(root: Entity, children: &Query<&Children>, names: &Query<&Name>) -> Option<Self> {
+        let bone = |bone_name: &str| {
+            children.iter_descendants(root).find(|entity| {
+                names
+                    .get(*entity)
+                    .is_ok_and(|name| name.as_str() == bone_name)
+            })
+        };
+        let leg = |side: &str| {
+            Some(LegChain {
+                upper: bone(&format!("UpperLeg.{side}"))?,
+                lower: bone(&format!("LowerLeg.{side}"))?,
+                paw: bone(&format!("Paw.{side}"))?,
+            })
+        };
+
+        Some(Self {
+            hips: bone("Hips")?,
+            legs: [leg("L")?, leg("R")?],
+        })
+    }
+}
+
+fn align_model_to_ground(
+    models: Query<(Entity, &mut GroundAlignment, &ChildOf, Option<&ModelLean>)>,
+    players: Query<(&CharacterBody, &StateMachine)>,
+    animators: Query<(Entity, &CharacterAnimator)>,
+    children: Query<&Children>,
+    names: Query<&Name>,
+    parents: Query<&ChildOf>,
+    mut transforms: Query<&mut Transform>,
+    spatial_query: SpatialQuery,
+    time: Res<Time>,
+) {
+    for (model, mut alignment, player, lean) in models {
+        let player = player.parent();
+        let Ok((body, state)) = players.get(player) else {
+            continue;
+        };
+
+        // The bones go away when the model is swapped
+        if alignment.rig.is_some_and(|rig| !names.contains(rig.hips)) {
+            alignment.rig = None;
+            alignment.animated_pose.clear();
+        }
+        if alignment.rig.is_none() {
+            alignment.rig = animators
+                .iter()
+                .find(|(_, animator)| animator.model == model)
+                .and_then(|(root, _)| LegRig::find(root, &children, &names));
+        }
+
+        // Tilt the whole model around the center of the body, models without the bones tilt too
+        let sliding = matches!(
+            state.movement_state,
+            MajorMoveState::Grounded(MinorGroundState::Sliding)
+        );
+        let player_rotation = world_transform(player, &transforms, &parents).rotation;
+        let target_tilt = if alignment.slide_tilt && sliding {
+            let tilt = Quat::from_rotation_arc(*body.up, *body.last_normal);
+            player_rotation.inverse() * tilt * player_rotation
+        } else {
+            Quat::IDENTITY
+        };
+        let smoothing = 1.0 - (-alignment.smoothing * time.delta_secs()).exp();
+        alignment.tilt = alignment.tilt.slerp(target_tilt, smoothing);
+
+        let Ok(mut model_transform) = transforms.get_mut(model) else {
+            continue;
+        };
+        let rest_translation = *alignment
+            .rest_translation
+            .get_or_insert(model_transform.translation);
+        model_transform.rotation =
+            alignment.tilt * lean.map_or(Quat::IDENTITY, ModelLean::rotation);
+        model_transform.translation = alignment.tilt * rest_translation;
+
+        let Some(rig) = alignment.rig else {
+            continue;
+        };
+
+        // Start from the animated pose instead of stacking on last frame's adjustments
+        for bone in rig.bones() {
+            let Ok(mut transform) = transforms.get_mut(bone) else {
+                continue;
+            };
+            if transform.is_changed() {
+                alignment.animated_pose.insert(bone, *transform);
+            } else if let Some(pose) = alignment.animated_pose.get(&bone) {
+                transform.set_if_neq(*pose);
+            }
+        }
+
+        // Find where each foot should stand, relative to how high it is animated above the model
+        let model_world = world_transform(model, &transforms, &parents);
+        let up = model_world.rotation * Vec3::Y;
+        let filter = SpatialQueryFilter::from_excluded_entities([player]);
+
+        let mut targets = [None; 2];
+        if alignment.foot_ik && body.grounded {
+            for (target, leg) in targets.iter_mut().zip(rig.legs) {
+                let paw = world_transform(leg.paw, &transforms, &parents).translation;
+                let Ok(down) = Dir3::new(-up) else {
+                    continue;
+                };
+
+                *target = spatial_query
+                    .cast_ray(
+                        paw + up * alignment.ray_up,
+                        down,
+                        alignment.ray_up + alignment.ray_down,
+                        true,
+                        &filter,
+                    )
+                    .map(|hit| {
+                        let hit_point = paw + up * (alignment.ray_up - hit.distance);
+                        let foot_height = (paw - model_world.translation).dot(up);
+                        hit_point + up * foot_height
+                    });
+            }
+        }
+
+        // Lower the hips for the foot that has to reach the furthest down
+        let target_offset = targets
+            .iter()
+            .zip(rig.legs)
+            .filter_map(|(target, leg)| {
+                let paw = world_transform(leg.paw, &transforms, &parents).translation;
+                target.map(|target| (target - paw).dot(up))
+            })
+            .fold(0.0_f32, f32::min)
+            .max(-alignment.max_pelvis_offset);
+        alignment.pelvis_offset += (target_offset - alignment.pelvis_offset) * smoothing;
+
+        if let Ok(hips_parent) = parents.get(rig.hips) {
+            let parent_world = world_transform(hips_parent.parent(), &transforms, &parents);
+            let offset = parent_world.rotation.inverse() * (up * alignment.pelvis_offset)
+                / parent_world.scale;
+            if let Ok(mut hips) = transforms.get_mut(rig.hips) {
+                hips.translation += offset;
+            }
+        }
+
+        for (target, leg) in targets.into_iter().zip(rig.legs) {
+            let Some(target) = target else {
+                continue;
+            };
+
+            let upper = world_transform(leg.upper, &transforms, &parents);
+            let lower = world_transform(leg.lower, &transforms, &parents);
+            let paw = world_transform(leg.paw, &transforms, &parents);
+
+            let (upper_rotation, lower_rotation) = solve_two_bone(
+                upper.translation,
+                lower.translation,
+                paw.translation,
+                target,
+                upper.rotation,
+                lower.rotation,
+            );
+            if let Ok(mut upper) = transforms.get_mut(leg.upper) {
+                upper.rotation *= upper_rotation;
+            }
+            if let Ok(mut lower) = transforms.get_mut(leg.lower) {
+                lower.rotation *= lower_rotation;
+            }
+        }
+    }
+}
+
+/// Transform of an entity in world space from the local transforms, since the global ones are a frame late
+fn world_transform(
+    entity: Entity,
+    transforms: &Query<&mut Transform>,
+    parents: &Query<&ChildOf>,
+) -> Transform {
+    let mut world = transforms.get(entity).copied().unwrap_or_default();
+    let mut current = entity;
+    while let Ok(child_of) = parents.get(current) {
+        current = child_of.parent();
+        if let Ok(parent) = transforms.get(current) {
+            world = parent.mul_transform(world);
+        }
+    }
+    world
+}
+
+/// Local rotations to apply to the upper and lower bones so the end of the chain reaches the target
+fn solve_two_bone(
+    upper: Vec3,
+    lower: Vec3,
+    end: Vec3,
+    target: Vec3,
+    upper_world: Quat,
+    lower_world: Quat,
+) -> (Quat, Quat) {
+    let angle = |a: Vec3, b: Vec3| {
+        a.normalize_or_zero()
+            .dot(b.normalize_or_zero())
+            .clamp(-1.0, 1.0)
+            .acos()
+    };
+
+    let upper_length = upper.distance(lower);
+    let lower_length = lower.distance(end);
+    let target_length = upper
+        .distance(target)
+        .clamp(0.01, upper_length + lower_length - 0.01);
+
+    // Angles at the hip and knee now and once the chain is as long as the target is far
+    let hip_angle = angle(end - upper, lower - upper);
+    let knee_angle = angle(upper - lower, end - lower);
+    let target_angle = angle(end - upper, target - upper);
+
+    let new_hip_angle = ((lower_length * lower_length
+        - upper_length * upper_length
+        - target_length * target_length)
+        / (-2.0 * upper_length * target_length).min(-f32::EPSILON))
+    .clamp(-1.0, 1.0)
+    .acos();
+    let new_knee_angle = ((target_length * target_length
+        - upper_length * upper_length
+        - lower_length * lower_length)
+        / (-2.0 * upper_length * lower_length).min(-f32::EPSILON))
+    .clamp(-1.0, 1.0)
+    .acos();
+
+    // Bend around the plane the leg already bends in so the knee keeps pointing the same way
+    let bend_axis = (end - upper).cross(lower - upper).normalize_or_zero();
+    let target_axis = (end - upper).cross(target - upper).normalize_or_zero();
+
+    // A straight leg has no plane to bend in
+    if bend_axis == Vec3::ZERO {
+        return (Quat::IDENTITY, Quat::IDENTITY);
+    }
+
+    let hip_bend =
+        Quat::from_axis_angle(upper_world.inverse() * bend_axis, new_hip_angle - hip_angle);
+    let knee_bend = Quat::from_axis_angle(
+        lower_world.inverse() * bend_axis,
+        new_knee_angle - knee_angle,
+    );
+
+    if target_axis == Vec3::ZERO {
+        return (hip_bend, knee_bend);
+    }
+    let hip_aim = Quat::from_axis_angle(upper_world.inverse() * target_axis, target_angle);
+    (hip_bend * hip_aim, knee_bend)
+}
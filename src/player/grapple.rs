@@ -0,0 +1,153 @@
+use super::camera::CameraPivot;
+use super::state_machine::*;
+use crate::character_body::CharacterBody;
+use crate::input::PlayerInput;
+
+use avian3d::prelude::*;
+use bevy::prelude::*;
+use leafwing_input_manager::prelude::*;
+
+pub(super) struct GrapplePlugin;
+impl Plugin for GrapplePlugin {
+    fn build(&self, app: &mut App) {
+        app.register_type::<Grapplable>()
+            .register_type::<GrappleRope>();
+
+        app.add_systems(Update, draw_grapple_rope);
+    }
+}
+
+/// Surface the player can attach a grapple rope to
+#[derive(Component, Reflect, Clone, Copy, Default, Debug)]
+#[reflect(Component, Default)]
+pub struct Grapplable;
+
+/// Rope attaching the player to a grapple point, only present while swinging
+#[derive(Component, Reflect, Clone, Copy, Debug)]
+#[reflect(Component)]
+pub struct GrappleRope {
+    pub anchor: Vec3,
+    pub length: f32,
+}
+
+const GRAPPLE_RANGE: f32 = 30.0;
+const MIN_ROPE_LENGTH: f32 = 1.0;
+/// Speed the rope pulls the player back in at for every unit it is stretched
+const ROPE_STIFFNESS: f32 = 10.0;
+
+pub(super) fn player_grapple(
+    mut commands: Commands,
+    cameras: Query<(&GlobalTransform, &ChildOf), With<Camera3d>>,
+    pivots: Query<&CameraPivot>,
+    mut players: Query<(
+        &Transform,
+        &mut StateMachine,
+        &mut CharacterBody,
+        &ActionState<PlayerInput>,
+        Option<&GrappleRope>,
+    )>,
+    grapplables: Query<(), With<Grapplable>>,
+    spatial_query: SpatialQuery,
+) {
+    for (camera_transform, parent) in cameras {
+        let Ok(pivot) = pivots.get(parent.0) else {
+            continue;
+        };
+        let Ok((transform, mut state, mut body, input, rope)) = players.get_mut(pivot.0) else {
+            continue;
+        };
+
+        if rope.is_some() {
+            let swinging = matches!(
+                state.movement_state,
+                MajorMoveState::Airborne(MinorAirborneState::Swinging)
+            );
+
+            // Rope is let go or the player jumped, dove or landed out of the swing
+            if !swinging || !input.pressed(&PlayerInput::Grapple) {
+                commands.entity(pivot.0).remove::<GrappleRope>();
+
+                if swinging {
+                    let _ = state.transition(MajorMoveState::Airborne(MinorAirborneState::Falling));
+                }
+            }
+            continue;
+        }
+
+        if !input.just_pressed(&PlayerInput::Grapple) {
+            continue;
+        }
+
+        let Some(hit) = spatial_query.cast_ray(
+            camera_transform.translation(),
+            camera_transform.forward(),
+            GRAPPLE_RANGE,
+            true,
+            &SpatialQueryFilter::from_excluded_entities([pivot.0]),
+        ) else {
+            continue;
+        };
+
+        if !grapplables.contains(hit.entity) {
+            continue;
+        }
+
+        let anchor = camera_transform.translation() + camera_transform.forward() * hit.distance;
+        let length = transform.translation.distance(anchor);
+        if length < MIN_ROPE_LENGTH || length > GRAPPLE_RANGE {
+            continue;
+        }
+
+        if state
+            .transition(MajorMoveState::Airborne(MinorAirborneState::Swinging))
+            .is_ok()
+        {
+            body.grounded = false;
+            commands
+                .entity(pivot.0)
+                .insert(GrappleRope { anchor, length });
+        }
+    }
+}
+
+/// Keep the player at rope length from the anchor, which makes gravity swing it around.
+/// Only the velocity is changed so the physics still stops the player at walls
+pub(super) fn player_swing(
+    players: Query<(&Transform, &mut LinearVelocity, &StateMachine, &GrappleRope)>,
+) {
+    for (transform, mut velocity, state, rope) in players {
+        if !matches!(
+            state.movement_state,
+            MajorMoveState::Airborne(MinorAirborneState::Swinging)
+        ) {
+            continue;
+        }
+
+        let offset = transform.translation - rope.anchor;
+        let stretch = offset.length() - rope.length;
+        if stretch < 0.0 {
+            continue;
+        }
+
+        let Some(outwards) = offset.try_normalize() else {
+            continue;
+        };
+
+        // Pull back in by how far the rope got stretched instead of snapping to its length
+        let outwards_speed = velocity.dot(outwards);
+        let target_speed = -stretch * ROPE_STIFFNESS;
+        if outwards_speed > target_speed {
+            velocity.0 += outwards * (target_speed - outwards_speed);
+        }
+    }
+}
+
+fn draw_grapple_rope(players: Query<(&GlobalTransform, &GrappleRope)>, mut gizmos: Gizmos) {
+    for (transform, rope) in players {
+        gizmos.line(
+            transform.translation(),
+            rope.anchor,
+            bevy::color::palettes::basic::SILVER,
+        );
+    }
+}
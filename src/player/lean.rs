@@ -0,0 +1,133 @@
+use super::state_machine::{MajorMoveState, MinorAirborneState, StateMachine};
+use crate::character_body::CharacterBody;
+
+use avian3d::prelude::*;
+use bevy::prelude::*;
+
+pub(super) struct LeanPlugin;
+impl Plugin for LeanPlugin {
+    fn build(&self, app: &mut App) {
+        app.register_type::<ModelLean>();
+
+        app.add_systems(Update, lean_model);
+    }
+}
+
+/// Put on the model child of the player to turn it smoothly and lean it into turns,
+/// the physics body never rotates so collisions stay the same
+#[derive(Component, Reflect, Clone, Debug)]
+#[reflect(Component)]
+pub struct ModelLean {
+    /// How fast the model turns toward where the player is going, in radians per second
+    pub turn_rate: f32,
+    /// Roll in radians for every unit of sideways acceleration
+    pub lean_per_acceleration: f32,
+    pub max_lean: f32,
+    /// Forward pitch in radians while diving
+    pub dive_pitch: f32,
+    /// How fast the lean and pitch catch up to their targets
+    pub smoothing: f32,
+
+    facing: Option<Dir3>,
+    last_velocity: Vec3,
+    lean: f32,
+    pitch: f32,
+    /// Rotation of the model relative to the player, before the ground alignment tilts it
+    rotation: Quat,
+}
+
+impl Default for ModelLean {
+    fn default() -> Self {
+        Self {
+            turn_rate: 12.0,
+            lean_per_acceleration: 0.01,
+            max_lean: 0.4,
+            dive_pitch: 0.8,
+            smoothing: 10.0,
+            facing: None,
+            last_velocity: Vec3::ZERO,
+            lean: 0.0,
+            pitch: 0.0,
+            rotation: Quat::IDENTITY,
+        }
+    }
+}
+
+impl ModelLean {
+    pub fn rotation(&self) -> Quat {
+        self.rotation
+    }
+}
+
+/// Turn and lean the model, the ground alignment adds its tilt on top when the model has one
+fn lean_model(
+    models: Query<(&mut ModelLean, &mut Transform, &ChildOf)>,
+    players: Query<
+        (&Transform, &LinearVelocity, &CharacterBody, &StateMachine),
+        Without<ModelLean>,
+    >,
+    time: Res<Time>,
+) {
+    let delta = time.delta_secs();
+    if delta <= 0.0 {
+        return;
+    }
+
+    for (mut lean, mut model_transform, player) in models {
+        let Ok((transform, velocity, body, state)) = players.get(player.parent()) else {
+            continue;
+        };
+
+        let flat_velocity = body.horizontal(velocity.0);
+
+        // Keep facing the same way when not moving, along the current up
+        let current_facing = lean
+            .facing
+            .and_then(|facing| Dir3::new(body.horizontal(*facing)).ok())
+            .or_else(|| Dir3::new(body.horizontal(transform.rotation * Vec3::Z)).ok());
+        let Some(current_facing) = current_facing else {
+            continue;
+        };
+        let facing = match Dir3::new(flat_velocity) {
+            Ok(target) if flat_velocity.length_squared() > 1.0 => current_facing.slerp(
+                target,
+                turn_fraction(current_facing, target, lean.turn_rate * delta),
+            ),
+            _ => current_facing,
+        };
+        lean.facing = Some(facing);
+
+        // Roll toward the side the player is accelerating to
+        let right = body.up.cross(*facing);
+        let acceleration = (flat_velocity - body.horizontal(lean.last_velocity)) / delta;
+        lean.last_velocity = velocity.0;
+        let target_lean = (-acceleration.dot(right) * lean.lean_per_acceleration)
+            .clamp(-lean.max_lean, lean.max_lean);
+
+        let target_pitch = match state.movement_state {
+            MajorMoveState::Airborne(MinorAirborneState::Dive) => lean.dive_pitch,
+            _ => 0.0,
+        };
+
+        let smoothing = 1.0 - (-lean.smoothing * delta).exp();
+        lean.lean += (target_lean - lean.lean) * smoothing;
+        lean.pitch += (target_pitch - lean.pitch) * smoothing;
+
+        let world_yaw = Transform::default().looking_to(-facing, body.up).rotation;
+        lean.rotation = transform.rotation.inverse()
+            * world_yaw
+            * Quat::from_rotation_x(lean.pitch)
+            * Quat::from_rotation_z(lean.lean);
+        model_transform.rotation = lean.rotation;
+    }
+}
+
+/// Fraction of the way between two directions covered by turning a set angle
+fn turn_fraction(from: Dir3, to: Dir3, max_angle: f32) -> f32 {
+    let angle = from.angle_between(*to);
+    if angle <= max_angle {
+        1.0
+    } else {
+        max_angle / angle
+    }
+}
@@ -1,4 +1,4 @@
-use crate::character_body::{CharacterBody, CharacterGroundSnap};
+use crate::character_body::{CharacterBody, CharacterGroundSnap, SurfaceProperties};
 use crate::input::PlayerInput;
 
 use avian3d::prelude::*;
@@ -6,9 +6,22 @@ use bevy::prelude::*;
 use leafwing_input_manager::prelude::*;
 
 use state_machine::*;
+use tuning::MovementTuning;
 
+pub mod animation;
+pub mod animation_events;
+pub mod boosters;
 pub mod camera;
+pub mod dash;
+pub mod foot_ik;
+pub mod grapple;
+pub mod lean;
+pub mod pickups;
+pub mod skins;
 pub mod state_machine;
+pub mod swimming;
+pub mod transitions;
+pub mod tuning;
 
 pub const PLAYER_HEIGHT: f32 = 1.0;
 pub const PLAYER_THICKNESS: f32 = 0.2;
@@ -19,19 +32,42 @@ impl Plugin for PlayerPlugin {
         app.register_type::<PlayerCharacterMarker>()
             .register_type::<PlayerMarker>();
 
-        app.add_plugins((camera::CameraPlugin, state_machine::StateMachinePlugin));
+        app.add_plugins((
+            animation::PlayerAnimationPlugin,
+            animation_events::AnimationEventsPlugin,
+            camera::CameraPlugin,
+            foot_ik::FootIkPlugin,
+            state_machine::StateMachinePlugin,
+            boosters::BoostersPlugin,
+            swimming::SwimmingPlugin,
+            grapple::GrapplePlugin,
+            lean::LeanPlugin,
+            pickups::PickupsPlugin,
+            skins::SkinsPlugin,
+            tuning::TuningPlugin,
+        ));
 
         app.add_systems(
             FixedUpdate,
             ((
                 player_check_floor,
+                swimming::player_detect_water,
                 player_reset_y_vel,
                 player_slide_and_crouch,
-                (player_gravity, player_movement),
+                (
+                    boosters::player_launch_pads,
+                    boosters::player_boost_rings,
+                    pickups::player_air_action_pickups,
+                ),
+                (player_gravity, player_movement, swimming::player_swim),
+                grapple::player_swing,
                 player_jump,
                 player_dive,
                 player_glide,
+                dash::player_dash,
+                grapple::player_grapple,
                 (player_rotation, player_tick_machine),
+                state_machine::emit_state_changes,
             )
                 .chain()
                 .after(PhysicsSystems::Last),),
@@ -46,13 +82,14 @@ impl Plugin for PlayerPlugin {
             up: Dir3::Y,
             max_dot_variance: 0.49,
             last_normal: Dir3::Y,
-            force_slide: false,
+            surface: SurfaceProperties::DEFAULT,
         },
         CharacterGroundSnap { distance: 0.5 },
         Collider::capsule(PLAYER_THICKNESS, PLAYER_HEIGHT-2.0*PLAYER_THICKNESS),
         PlayerMarker,
         PlayerLookDirection,
         StateMachine,
+        swimming::Submersion,
         SleepingDisabled,
 )]
 #[reflect(Component)]
@@ -66,17 +103,20 @@ pub struct PlayerLookDirection(pub Vec3);
 #[reflect(Component)]
 pub struct PlayerMarker;
 
-fn player_reset_y_vel(players: Query<(&mut LinearVelocity, &StateMachine)>, time: Res<Time>) {
-    for (mut velocity, state) in players {
+fn player_reset_y_vel(
+    players: Query<(&mut LinearVelocity, &StateMachine, &CharacterBody)>,
+    time: Res<Time>,
+) {
+    for (mut velocity, state, body) in players {
         if state.set_y_0() {
-            velocity.y = velocity.y.lerp(0.0, time.delta_secs() * 10.0);
+            let vertical_speed = body
+                .vertical_speed(velocity.0)
+                .lerp(0.0, time.delta_secs() * 10.0);
+            velocity.0 = body.with_vertical_speed(velocity.0, vertical_speed);
         }
     }
 }
 
-const MIN_SLIDE_VEL: f32 = 7.5;
-const MAX_SLIDE_VEL: f32 = 7.5;
-
 fn player_slide_and_crouch(
     players: Query<(
         &mut StateMachine,
@@ -87,7 +127,7 @@ fn player_slide_and_crouch(
 ) {
     for (mut state, velocity, input, body) in players {
         // Return early if slide is forced
-        if body.force_slide && state.is_grounded() && velocity.length_squared() > 0.001 {
+        if body.surface.force_slide && state.is_grounded() && velocity.length_squared() > 0.001 {
             let _ = state.transition(MajorMoveState::Grounded(MinorGroundState::Sliding));
             continue;
         }
@@ -97,7 +137,7 @@ fn player_slide_and_crouch(
                 // Slide if you can
                 MinorGroundState::Moving => {
                     if input.pressed(&PlayerInput::Crouch) {
-                        if velocity.length() > MIN_SLIDE_VEL {
+                        if velocity.length() > state.tuning.min_slide_speed {
                             let _ = state
                                 .transition(MajorMoveState::Grounded(MinorGroundState::Sliding));
                         } else {
@@ -111,20 +151,22 @@ fn player_slide_and_crouch(
                         let _ =
                             state.transition(MajorMoveState::Grounded(MinorGroundState::Moving));
                     }
-                    if velocity.length() > MIN_SLIDE_VEL {
+                    if velocity.length() > state.tuning.min_slide_speed {
                         let _ =
                             state.transition(MajorMoveState::Grounded(MinorGroundState::Sliding));
                     }
                 }
                 // Check if it can still slide
                 MinorGroundState::Sliding => {
-                    if velocity.length() < MAX_SLIDE_VEL || !input.pressed(&PlayerInput::Crouch) {
+                    if velocity.length() < state.tuning.stop_slide_speed
+                        || !input.pressed(&PlayerInput::Crouch)
+                    {
                         let _ =
                             state.transition(MajorMoveState::Grounded(MinorGroundState::Moving));
                     }
                 }
             },
-            MajorMoveState::Airborne(_) => continue,
+            MajorMoveState::Airborne(_) | MajorMoveState::Swimming(_) => continue,
         }
     }
 }
@@ -135,29 +177,62 @@ fn player_movement(
         &ActionState<PlayerInput>,
         &PlayerLookDirection,
         &StateMachine,
+        &CharacterBody,
     )>,
     time: Res<Time>,
 ) {
-    for (mut velocity, input, look_direction, state) in players {
-        let movement_stats = state.movement_stats();
+    for (mut velocity, input, look_direction, state, body) in players {
+        if state.is_swimming()
+            || matches!(
+                state.movement_state,
+                MajorMoveState::Airborne(MinorAirborneState::Dash(_))
+            )
+        {
+            continue;
+        }
 
-        let mut input_direction = input.axis_pair(&PlayerInput::Move);
-        input_direction.y = -input_direction.y;
+        let movement_stats = if state.is_grounded() {
+            state.movement_stats().on_surface(&body.surface)
+        } else {
+            state.movement_stats()
+        };
 
-        let look_dir = Dir2::new(look_direction.0.xz()).unwrap_or(Dir2::Y);
+        let input_axis = input.axis_pair(&PlayerInput::Move);
 
-        input_direction = input_direction
-            .rotate(*look_dir)
-            .rotate(Vec2::from_angle(std::f32::consts::PI / 2.0));
+        let forward = body
+            .horizontal(look_direction.0)
+            .try_normalize()
+            .unwrap_or_else(|| body.up.any_orthonormal_vector());
+        let right = forward.cross(*body.up);
 
-        let flat_velocity = velocity.xz();
+        let input_direction = forward * input_axis.y + right * input_axis.x;
 
-        if flat_velocity.length() > movement_stats.max_speed * 1.01
-            && input_direction.length_squared() > 0.01
-        {
-            if input_direction.length_squared() > 0.01 {
-                let simmilarity = input_direction.dot(flat_velocity).max(0.0);
+        let flat_velocity = body.horizontal(velocity.0);
+        let vertical_velocity = velocity.0 - flat_velocity;
+
+        let above_cap = flat_velocity.length() > movement_stats.max_speed * 1.01;
+        let steering = input_direction.length_squared() > 0.01;
+
+        // Keep momentum from slides and boosts while not steering in the air
+        if above_cap && !steering && !state.is_grounded() {
+            continue;
+        }
+
+        if above_cap && steering {
+            let simmilarity = input_direction.dot(flat_velocity).max(0.0);
 
+            // Turn without losing speed in the air unless steering against the movement
+            if !state.is_grounded() && simmilarity > 0.0 {
+                let speed = flat_velocity.length();
+                let target_velocity = input_direction.normalize() * speed;
+
+                let moved_flat_vel = flat_velocity.move_towards(
+                    target_velocity,
+                    time.delta_secs() * movement_stats.rotation_rate,
+                );
+
+                velocity.0 = moved_flat_vel.normalize_or_zero() * speed + vertical_velocity;
+            } else {
                 let target_velocity = simmilarity * input_direction;
 
                 let moved_flat_vel = flat_velocity.move_towards(
@@ -165,25 +240,31 @@ fn player_movement(
                     time.delta_secs() * movement_stats.rotation_rate,
                 );
 
-                velocity.x = moved_flat_vel.x;
-                velocity.z = moved_flat_vel.y;
+                velocity.0 = moved_flat_vel + vertical_velocity;
             }
         } else {
             let target_velocity = input_direction * movement_stats.max_speed;
 
-            let moved_flat_vel = flat_velocity.move_towards(
-                target_velocity,
-                time.delta_secs() * movement_stats.acceleration,
-            );
+            let rate = if target_velocity.length_squared() > flat_velocity.length_squared() {
+                movement_stats.acceleration
+            } else {
+                movement_stats.deceleration
+            };
+
+            let moved_flat_vel =
+                flat_velocity.move_towards(target_velocity, time.delta_secs() * rate);
 
-            velocity.x = moved_flat_vel.x;
-            velocity.z = moved_flat_vel.y;
+            velocity.0 = moved_flat_vel + vertical_velocity;
         }
     }
 }
 
 fn player_check_floor(players: Query<(&mut StateMachine, &CharacterBody)>) {
     for (mut machine, body) in players {
+        if machine.is_swimming() {
+            continue;
+        }
+
         if machine.is_grounded() && !body.grounded {
             let _ = machine.transition(MajorMoveState::Airborne(MinorAirborneState::Falling));
         }
@@ -194,19 +275,26 @@ fn player_check_floor(players: Query<(&mut StateMachine, &CharacterBody)>) {
     }
 }
 
-fn player_gravity(players: Query<(&mut LinearVelocity, &StateMachine)>, time: Res<Time>) {
-    for (mut velocity, state) in players {
+fn player_gravity(
+    players: Query<(&mut LinearVelocity, &StateMachine, &CharacterBody)>,
+    time: Res<Time>,
+) {
+    for (mut velocity, state, body) in players {
         let (up_gravity, down_gravity, terminal_velocity) = state.gravity();
 
-        if velocity.y > 0.0 {
-            velocity.y -= time.delta_secs() * up_gravity;
+        let mut vertical_speed = body.vertical_speed(velocity.0);
+
+        if vertical_speed > 0.0 {
+            vertical_speed -= time.delta_secs() * up_gravity;
         } else {
-            velocity.y -= time.delta_secs() * down_gravity;
+            vertical_speed -= time.delta_secs() * down_gravity;
         }
 
-        if velocity.y < -terminal_velocity {
-            velocity.y = -terminal_velocity;
+        if vertical_speed < -terminal_velocity {
+            vertical_speed = -terminal_velocity;
         }
+
+        velocity.0 = body.with_vertical_speed(velocity.0, vertical_speed);
     }
 }
 
@@ -222,28 +310,58 @@ fn player_jump(
     for (mut velocity, mut state, mut body, input) in players {
         let mut stop_jump = false;
 
-        if input.pressed(&PlayerInput::Jump) {
-            let final_state = state.jump();
+        if input.just_pressed(&PlayerInput::Jump) {
+            state.buffer_jump();
+        }
+
+        // Holding jump to glide into a grapple shouldn't let go of the rope right away
+        let swinging = matches!(
+            state.movement_state,
+            MajorMoveState::Airborne(MinorAirborneState::Swinging)
+        );
+        let wants_jump = state.jump_buffered() || (!swinging && input.pressed(&PlayerInput::Jump));
+
+        if wants_jump {
+            let was_jumping = matches!(
+                state.movement_state,
+                MajorMoveState::Airborne(MinorAirborneState::Jumping(_))
+            );
 
-            if final_state.is_ok() {
+            let speed = body.horizontal(velocity.0).length();
+            // Only a new press can use up an air jump
+            if let Ok(new_state) = state.jump(input.just_pressed(&PlayerInput::Jump), speed) {
+                state.clear_jump_buffer();
                 body.grounded = false;
-                velocity.y = state.jump_strength();
+                velocity.0 = body.with_vertical_speed(velocity.0, state.jump_strength());
+
+                if !was_jumping
+                    && matches!(
+                        new_state,
+                        MajorMoveState::Airborne(MinorAirborneState::Jumping(JumpType::Long(_)))
+                    )
+                {
+                    velocity.0 = long_jump_boost(velocity.0, &body, &state.tuning);
+                }
             }
-        } else {
+        }
+
+        if !input.pressed(&PlayerInput::Jump) {
             stop_jump = true;
         }
 
-        if velocity.y < 0.0 {
+        if body.vertical_speed(velocity.0) < 0.0 {
             stop_jump = true;
         }
 
         match &mut state.movement_state {
-            MajorMoveState::Grounded(_) => {}
+            MajorMoveState::Grounded(_) | MajorMoveState::Swimming(_) => {}
             MajorMoveState::Airborne(substate) => match substate {
                 MinorAirborneState::Jumping(jump_type) => match jump_type {
                     JumpType::Normal(time_left)
                     | JumpType::Dive(time_left)
-                    | JumpType::Crouch(time_left) => {
+                    | JumpType::Crouch(time_left)
+                    | JumpType::Double(time_left)
+                    | JumpType::Long(time_left) => {
                         *time_left -= time.delta_secs();
 
                         if *time_left <= 0.0 {
@@ -258,7 +376,7 @@ fn player_jump(
 
         if stop_jump {
             match &state.movement_state {
-                MajorMoveState::Grounded(_) => {}
+                MajorMoveState::Grounded(_) | MajorMoveState::Swimming(_) => {}
                 MajorMoveState::Airborne(substate) => match substate {
                     MinorAirborneState::Jumping(_) => {
                         let _ =
@@ -271,29 +389,33 @@ fn player_jump(
     }
 }
 
+/// Add the long jump boost along the slide direction without slowing down faster slides
+fn long_jump_boost(velocity: Vec3, body: &CharacterBody, tuning: &MovementTuning) -> Vec3 {
+    let flat_velocity = body.horizontal(velocity);
+    let speed = flat_velocity.length();
+    let boosted_speed = (speed + tuning.long_jump.boost).min(tuning.long_jump.max_speed.max(speed));
+
+    velocity - flat_velocity + flat_velocity.normalize_or_zero() * boosted_speed
+}
+
 fn player_dive(
     players: Query<(
         &mut LinearVelocity,
         &mut StateMachine,
         &ActionState<PlayerInput>,
+        &CharacterBody,
     )>,
 ) {
-    for (mut velocity, mut state, input) in players {
-        match &mut state.movement_state {
-            MajorMoveState::Grounded(_) => {}
-            MajorMoveState::Airborne(substate) => match substate {
-                MinorAirborneState::Dive => {}
-                _ => {
-                    if input.just_pressed(&PlayerInput::Crouch) && state.can_dive {
-                        if let Ok(_) =
-                            state.transition(MajorMoveState::Airborne(MinorAirborneState::Dive))
-                        {
-                            velocity.y = 1.0;
-                            state.can_dive = false;
-                        }
-                    }
-                }
-            },
+    for (mut velocity, mut state, input, body) in players {
+        // On the ground crouch slides and crouches instead
+        if input.just_pressed(&PlayerInput::Crouch)
+            && matches!(state.movement_state, MajorMoveState::Airborne(_))
+        {
+            state.buffer_dive();
+        }
+
+        if state.use_buffered_dive().is_some() {
+            velocity.0 = body.with_vertical_speed(velocity.0, 1.0);
         }
     }
 }
@@ -303,13 +425,16 @@ fn player_glide(
         &LinearVelocity,
         &mut StateMachine,
         &ActionState<PlayerInput>,
+        &CharacterBody,
     )>,
 ) {
-    for (velocity, mut state, input) in players {
+    for (velocity, mut state, input, body) in players {
         match &mut state.movement_state {
-            MajorMoveState::Grounded(_) => {}
+            MajorMoveState::Grounded(_) | MajorMoveState::Swimming(_) => {}
             MajorMoveState::Airborne(substance) => match substance {
-                MinorAirborneState::Dive => {}
+                MinorAirborneState::Dive
+                | MinorAirborneState::Swinging
+                | MinorAirborneState::Dash(_) => {}
                 MinorAirborneState::Glide => {
                     if !input.pressed(&PlayerInput::Jump) {
                         let _ =
@@ -317,7 +442,7 @@ fn player_glide(
                     }
                 }
                 _ => {
-                    if velocity.y <= 0.0 && input.pressed(&PlayerInput::Jump) {
+                    if body.vertical_speed(velocity.0) <= 0.0 && input.pressed(&PlayerInput::Jump) {
                         let _ =
                             state.transition(MajorMoveState::Airborne(MinorAirborneState::Glide));
                     }
@@ -327,17 +452,12 @@ fn player_glide(
     }
 }
 
-fn player_rotation(players: Query<(&mut Transform, &LinearVelocity)>) {
-    for (mut transform, velocity) in players {
-        let flat_vel = velocity.xz();
-
-        if flat_vel.length_squared() <= 1.0 {
-            continue;
-        }
-
-        let angle = Vec2::Y.angle_to(flat_vel);
-
-        transform.rotation = Quat::from_euler(EulerRot::YXZ, -angle, 0.0, 0.0);
+/// Keep the body upright along its up, turning toward the velocity is left to the model
+fn player_rotation(players: Query<(&mut Transform, &CharacterBody)>) {
+    for (mut transform, body) in players {
+        let current_up = transform.rotation * Vec3::Y;
+        transform.rotation =
+            (Quat::from_rotation_arc(current_up, *body.up) * transform.rotation).normalize();
     }
 }
 
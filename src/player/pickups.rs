@@ -0,0 +1,71 @@
+use super::state_machine::*;
+use crate::character_body::CharacterContacts;
+
+use avian3d::prelude::*;
+use bevy::prelude::*;
+
+pub(super) struct PickupsPlugin;
+impl Plugin for PickupsPlugin {
+    fn build(&self, app: &mut App) {
+        app.register_type::<AirActionPickup>();
+    }
+}
+
+/// Changes the air actions of the player going through it
+#[derive(Component, Reflect, Clone, Copy, Debug)]
+#[require(Sensor)]
+#[reflect(Component, Default)]
+pub struct AirActionPickup {
+    pub actions: AirActions,
+    pub mode: PickupMode,
+}
+
+impl Default for AirActionPickup {
+    fn default() -> Self {
+        Self {
+            actions: AirActions {
+                jumps: 1,
+                dives: 1,
+                glides: 0,
+                dashes: 1,
+            },
+            mode: PickupMode::Grant,
+        }
+    }
+}
+
+#[derive(Reflect, Clone, Copy, Default, Debug, PartialEq)]
+pub enum PickupMode {
+    /// Add the actions on top of what's left until the next landing
+    #[default]
+    Grant,
+    /// Replace what's left and what is refilled on landing with the actions
+    Limit,
+    /// Go back to refilling what the tuning says on landing
+    ClearLimit,
+}
+
+pub(super) fn player_air_action_pickups(
+    players: Query<(&mut StateMachine, &CharacterContacts)>,
+    pickups: Query<&AirActionPickup>,
+) {
+    for (mut state, contacts) in players {
+        for entered in contacts.entered() {
+            let Ok(pickup) = pickups.get(entered) else {
+                continue;
+            };
+
+            match pickup.mode {
+                PickupMode::Grant => state.air_actions.grant(pickup.actions),
+                PickupMode::Limit => {
+                    state.air_action_limit = Some(pickup.actions);
+                    state.air_actions = pickup.actions;
+                }
+                PickupMode::ClearLimit => {
+                    state.air_action_limit = None;
+                    state.refill_air_actions();
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,271 @@
+use super::PlayerMarker;
+use super::animation::{CharacterModel, PlayerAnimations};
+use crate::ron_asset::RonAssetPlugin;
+
+use bevy::gltf::GltfMaterialName;
+use bevy::prelude::*;
+use bevy::scene::SceneInstance;
+use serde::Deserialize;
+
+use std::collections::HashMap;
+
+const SKINS_PATH: &str = "player.skins.ron";
+
+pub(super) struct SkinsPlugin;
+impl Plugin for SkinsPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_plugins(RonAssetPlugin::<SkinRegistry>::new(&["skins.ron"]));
+
+        app.init_resource::<SkinSelection>();
+
+        app.add_systems(Startup, load_skin_registry);
+        app.add_systems(
+            Update,
+            (
+                skin_menu_input,
+                update_skin_menu,
+                swap_to_pending_skin,
+                apply_skin_textures,
+            )
+                .chain(),
+        );
+    }
+}
+
+/// Every model the player can be, loaded from `player.skins.ron`
+#[derive(Asset, TypePath, Deserialize, Clone, Debug)]
+pub struct SkinRegistry {
+    pub skins: Vec<Skin>,
+}
+
+#[derive(Deserialize, Clone, Debug)]
+pub struct Skin {
+    pub name: String,
+    /// glTF with the scene and the clips
+    pub model: String,
+    /// Which clips play in each movement state, every state clip has to be in the model
+    pub animations: String,
+    /// Image used as the base color of each material, by material name
+    pub textures: HashMap<String, String>,
+}
+
+#[derive(Resource)]
+struct SkinRegistryHandle(Handle<SkinRegistry>);
+
+/// Skin the player is using and the one waiting for its assets to load
+#[derive(Resource, Default)]
+struct SkinSelection {
+    current: usize,
+    pending: Option<PendingSkin>,
+}
+
+struct PendingSkin {
+    index: usize,
+    gltf: Handle<Gltf>,
+    animations: Handle<PlayerAnimations>,
+    textures: HashMap<String, Handle<Image>>,
+}
+
+/// Base color overrides of a model, applied to its materials as its scene spawns
+#[derive(Component, Clone, Default)]
+pub struct SkinTextures(HashMap<String, Handle<Image>>);
+
+/// Text listing the skins, only exists while the menu is open
+#[derive(Component, Default)]
+struct SkinMenu {
+    highlighted: usize,
+}
+
+fn load_skin_registry(mut commands: Commands, asset_server: Res<AssetServer>) {
+    commands.insert_resource(SkinRegistryHandle(asset_server.load(SKINS_PATH)));
+}
+
+/// Tab opens the menu, the arrows pick a skin and enter loads it
+fn skin_menu_input(
+    mut commands: Commands,
+    keys: Res<ButtonInput<KeyCode>>,
+    menus: Query<(Entity, &mut SkinMenu)>,
+    registry: Res<SkinRegistryHandle>,
+    registries: Res<Assets<SkinRegistry>>,
+    mut selection: ResMut<SkinSelection>,
+    asset_server: Res<AssetServer>,
+) {
+    if keys.just_pressed(KeyCode::Tab) {
+        if menus.is_empty() {
+            commands.spawn((
+                Name::new("Skin menu"),
+                SkinMenu {
+                    highlighted: selection.current,
+                },
+                Text::default(),
+                Node {
+                    position_type: PositionType::Absolute,
+                    top: Val::Px(40.0),
+                    left: Val::Px(10.0),
+                    ..default()
+                },
+            ));
+        } else {
+            for (menu, _) in &menus {
+                commands.entity(menu).despawn();
+            }
+        }
+        return;
+    }
+
+    let Some(registry) = registries.get(&registry.0) else {
+        return;
+    };
+    let skin_count = registry.skins.len();
+    if skin_count == 0 {
+        return;
+    }
+
+    for (_, mut menu) in menus {
+        if keys.just_pressed(KeyCode::ArrowDown) {
+            menu.highlighted = (menu.highlighted + 1) % skin_count;
+        }
+        if keys.just_pressed(KeyCode::ArrowUp) {
+            menu.highlighted = (menu.highlighted + skin_count - 1) % skin_count;
+        }
+
+        if keys.just_pressed(KeyCode::Enter) {
+            let index = menu.highlighted.min(skin_count - 1);
+            let skin = &registry.skins[index];
+            selection.pending = Some(PendingSkin {
+                index,
+                gltf: asset_server.load(&skin.model),
+                animations: asset_server.load(&skin.animations),
+                textures: skin
+                    .textures
+                    .iter()
+                    .map(|(material, image)| (material.clone(), asset_server.load(image)))
+                    .collect(),
+            });
+        }
+    }
+}
+
+fn update_skin_menu(
+    menus: Query<(&SkinMenu, &mut Text)>,
+    registry: Res<SkinRegistryHandle>,
+    registries: Res<Assets<SkinRegistry>>,
+    selection: Res<SkinSelection>,
+) {
+    let Some(registry) = registries.get(&registry.0) else {
+        return;
+    };
+
+    for (menu, mut text) in menus {
+        let mut lines = vec!["Skins (arrows to pick, enter to load)".to_string()];
+        for (index, skin) in registry.skins.iter().enumerate() {
+            let cursor = if index == menu.highlighted { ">" } else { " " };
+            let status = if selection
+                .pending
+                .as_ref()
+                .is_some_and(|pending| pending.index == index)
+            {
+                " (loading)"
+            } else if index == selection.current {
+                " (current)"
+            } else {
+                ""
+            };
+            lines.push(format!("{cursor} {}{status}", skin.name));
+        }
+        text.0 = lines.join("\n");
+    }
+}
+
+/// Respawn the player model once the chosen skin is loaded, if it has every clip it needs
+fn swap_to_pending_skin(
+    mut commands: Commands,
+    mut selection: ResMut<SkinSelection>,
+    asset_server: Res<AssetServer>,
+    gltfs: Res<Assets<Gltf>>,
+    mappings: Res<Assets<PlayerAnimations>>,
+    models: Query<(Entity, &ChildOf), With<CharacterModel>>,
+    players: Query<(), With<PlayerMarker>>,
+) {
+    let Some(pending) = &selection.pending else {
+        return;
+    };
+
+    let failed = asset_server
+        .get_load_state(&pending.gltf)
+        .is_some_and(|state| state.is_failed())
+        || asset_server
+            .get_load_state(&pending.animations)
+            .is_some_and(|state| state.is_failed());
+    if failed {
+        warn!("Skin {} failed to load", pending.index);
+        selection.pending = None;
+        return;
+    }
+
+    let (Some(gltf), Some(mapping)) = (gltfs.get(&pending.gltf), mappings.get(&pending.animations))
+    else {
+        return;
+    };
+
+    let missing = mapping.missing_clips(gltf);
+    if !missing.is_empty() {
+        warn!(
+            "Skin {} can't be used, its model is missing the clips {missing:?}",
+            pending.index
+        );
+        selection.pending = None;
+        return;
+    }
+
+    for (model, player) in models {
+        if !players.contains(player.parent()) {
+            continue;
+        }
+
+        // The old scene goes away with its animation player, which gets linked again to the new one
+        commands
+            .entity(model)
+            .despawn_related::<Children>()
+            .remove::<(SceneRoot, SceneInstance)>()
+            .insert((
+                CharacterModel::new(pending.gltf.clone(), pending.animations.clone()),
+                SkinTextures(pending.textures.clone()),
+            ));
+    }
+
+    selection.current = pending.index;
+    selection.pending = None;
+}
+
+fn apply_skin_textures(
+    mut commands: Commands,
+    meshes: Query<
+        (Entity, &GltfMaterialName, &MeshMaterial3d<StandardMaterial>),
+        Added<GltfMaterialName>,
+    >,
+    parents: Query<&ChildOf>,
+    skins: Query<&SkinTextures>,
+    mut materials: ResMut<Assets<StandardMaterial>>,
+) {
+    for (entity, material_name, material) in meshes {
+        let Some(skin) = parents
+            .iter_ancestors(entity)
+            .find_map(|ancestor| skins.get(ancestor).ok())
+        else {
+            continue;
+        };
+        let Some(texture) = skin.0.get(&material_name.0) else {
+            continue;
+        };
+        let Some(mut skinned) = materials.get(&material.0).cloned() else {
+            continue;
+        };
+
+        // The material is shared with every other instance of the model, so it gets copied
+        skinned.base_color_texture = Some(texture.clone());
+        commands
+            .entity(entity)
+            .insert(MeshMaterial3d(materials.add(skinned)));
+    }
+}
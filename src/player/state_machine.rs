@@ -1,25 +1,91 @@
+use super::transitions::{TransitionError, check_transition, resolve_transition};
+use super::tuning::{MovementTuning, StateTuning};
+use crate::character_body::SurfaceProperties;
+
 use bevy::prelude::*;
+use serde::Deserialize;
+
+use std::collections::VecDeque;
 
 pub(super) struct StateMachinePlugin;
 impl Plugin for StateMachinePlugin {
     fn build(&self, app: &mut App) {
         app.register_type::<StateMachine>();
+
+        app.add_message::<MoveStateChanged>();
+
+        #[cfg(debug_assertions)]
+        if let Err(errors) =
+            super::transitions::validate_transitions(super::transitions::TRANSITIONS)
+        {
+            panic!("Invalid movement state transition table: {errors:?}");
+        }
     }
 }
 
+/// How many transitions a state machine remembers
+const HISTORY_LENGTH: usize = 16;
+
 #[derive(Component, Reflect, Clone, Default)]
 #[reflect(Component)]
 pub struct StateMachine {
     pub movement_state: MajorMoveState,
     coyote_timer: f32,
+    jump_buffer_timer: f32,
+    dive_buffer_timer: f32,
     pub stuck_in_state_timer: f32,
-    pub can_dive: bool,
+    /// Air actions left until the next landing
+    pub air_actions: AirActions,
+    /// Overrides the air actions refilled on landing, set by pickups
+    pub air_action_limit: Option<AirActions>,
+    /// Feel numbers of every state, copied from the loaded tuning asset
+    pub tuning: MovementTuning,
+    /// Amount of times the machine was ticked
+    ticks: u64,
+    /// Last transitions, oldest first
+    history: VecDeque<StateChange>,
+    /// Transitions at the end of the history that weren't sent as events yet
+    unreported_changes: usize,
+}
+
+#[derive(Reflect, Clone, Debug)]
+pub struct StateChange {
+    pub from: MajorMoveState,
+    pub to: MajorMoveState,
+    /// Tick of the state machine the change happened on
+    pub tick: u64,
+}
+
+/// Sent for every successful transition of a state machine
+#[derive(Message, Clone, Debug)]
+pub struct MoveStateChanged {
+    pub entity: Entity,
+    pub from: MajorMoveState,
+    pub to: MajorMoveState,
+    pub tick: u64,
+}
+
+/// Triggered on an entity when its state machine enters a state
+#[derive(EntityEvent, Clone, Debug)]
+pub struct MoveStateEntered {
+    pub entity: Entity,
+    pub state: MajorMoveState,
+    pub from: MajorMoveState,
 }
 
-#[derive(Reflect, Clone)]
+/// Triggered on an entity when its state machine exits a state
+#[derive(EntityEvent, Clone, Debug)]
+pub struct MoveStateExited {
+    pub entity: Entity,
+    pub state: MajorMoveState,
+    pub to: MajorMoveState,
+}
+
+#[derive(Reflect, Clone, Debug, PartialEq)]
 pub enum MajorMoveState {
     Grounded(MinorGroundState),
     Airborne(MinorAirborneState),
+    Swimming(MinorSwimState),
 }
 
 impl Default for MajorMoveState {
@@ -28,7 +94,7 @@ impl Default for MajorMoveState {
     }
 }
 
-#[derive(Clone, Default, Reflect)]
+#[derive(Clone, Default, Reflect, Debug, PartialEq)]
 pub enum MinorGroundState {
     #[default]
     Moving,
@@ -36,29 +102,129 @@ pub enum MinorGroundState {
     Crouched,
 }
 
-#[derive(Clone, Default, Reflect)]
+#[derive(Clone, Default, Reflect, Debug, PartialEq)]
 pub enum MinorAirborneState {
     #[default]
     Falling,
     Jumping(JumpType),
     Dive,
     Glide,
+    /// Hanging from a grapple rope
+    Swinging,
+    /// Moving in a straight line without gravity, stuck in the state until the dash ends
+    Dash(Dir3),
+}
+
+#[derive(Clone, Default, Reflect, Debug, PartialEq)]
+pub enum MinorSwimState {
+    /// Floating with the head out of the water
+    #[default]
+    Surface,
+    Underwater,
 }
 
-#[derive(Clone, Copy, Reflect)]
+#[derive(Clone, Copy, Reflect, Debug, PartialEq)]
 /// Internal f32 to count how much time left there is on the jump
 pub enum JumpType {
     Normal(f32),
     Crouch(f32),
     Dive(f32),
+    /// Jump done in the air using an air action
+    Double(f32),
+    /// Jump out of a slide that keeps its speed
+    Long(f32),
+}
+
+/// Amount of each air action left, refilled when landing
+#[derive(Clone, Copy, Debug, Default, PartialEq, Reflect, Deserialize)]
+pub struct AirActions {
+    pub jumps: u8,
+    pub dives: u8,
+    pub glides: u8,
+    pub dashes: u8,
+}
+
+impl AirActions {
+    /// Amount of an air action that never runs out
+    pub const UNLIMITED: u8 = u8::MAX;
+
+    /// Use up one of an air action, returns false if there were none left
+    pub fn consume(amount: &mut u8) -> bool {
+        match *amount {
+            0 => false,
+            Self::UNLIMITED => true,
+            _ => {
+                *amount -= 1;
+                true
+            }
+        }
+    }
+
+    /// Add the air actions of another budget, staying unlimited if either one is
+    pub fn grant(&mut self, other: AirActions) {
+        fn add(amount: &mut u8, other: u8) {
+            *amount = if *amount == AirActions::UNLIMITED || other == AirActions::UNLIMITED {
+                AirActions::UNLIMITED
+            } else {
+                amount.saturating_add(other).min(AirActions::UNLIMITED - 1)
+            };
+        }
+
+        add(&mut self.jumps, other.jumps);
+        add(&mut self.dives, other.dives);
+        add(&mut self.glides, other.glides);
+        add(&mut self.dashes, other.dashes);
+    }
 }
 
 pub trait PlayerStateMachine {
-    /// Try to get into a jumping state and return the new state
-    fn jump(&mut self) -> Result<MajorMoveState, MajorMoveState>;
+    /// Try to get into a jumping state and return the new state. With `air_jump` an air jump can be
+    /// used, the transition table decides which jump wins when both are possible. `speed` is the
+    /// horizontal speed, only a fast enough slide turns into a long jump
+    fn jump(&mut self, air_jump: bool, speed: f32) -> Result<MajorMoveState, TransitionError>;
+
+    /// Transition to a new state if the transition table allows it and its guard passes. Returns the new state
+    fn transition(&mut self, new_state: MajorMoveState) -> Result<MajorMoveState, TransitionError>;
+
+    /// Transition to the candidate with the highest priority rule whose guard passes. Returns the new state
+    fn transition_by_priority(
+        &mut self,
+        candidates: Vec<MajorMoveState>,
+    ) -> Result<MajorMoveState, TransitionError>;
+
+    /// Transition to a new state ignoring guards and lose coyote time. Still fails if the transition is illegal
+    fn force_transition(
+        &mut self,
+        new_state: MajorMoveState,
+    ) -> Result<MajorMoveState, TransitionError>;
+
+    /// Remember a jump press so it happens on the first tick a jump is possible
+    fn buffer_jump(&mut self);
+
+    /// Remember a dive press so it happens on the first tick a dive is possible
+    fn buffer_dive(&mut self);
+
+    /// Check if there is a jump press waiting to be used
+    fn jump_buffered(&self) -> bool;
+
+    /// Check if there is a dive press waiting to be used
+    fn dive_buffered(&self) -> bool;
 
-    /// Transition to a new state unless stuck. Returns Ok(Old state) Err(New state)
-    fn transition(&mut self, new_state: MajorMoveState) -> Result<MajorMoveState, MajorMoveState>;
+    /// Forget the buffered jump press after using it
+    fn clear_jump_buffer(&mut self);
+
+    /// Forget the buffered dive press after using it
+    fn clear_dive_buffer(&mut self);
+
+    /// Dive in the air if a dive press is buffered and a dive is possible, using up the press.
+    /// Returns the new state
+    fn use_buffered_dive(&mut self) -> Option<MajorMoveState>;
+
+    /// Called right after entering a state
+    fn on_enter(&mut self, from: &MajorMoveState);
+
+    /// Called right before exiting a state
+    fn on_exit(&mut self, to: &MajorMoveState);
 
     /// Update the state of the state machine
     fn tick(&mut self, time: Time) -> ();
@@ -69,6 +235,9 @@ pub trait PlayerStateMachine {
     /// Check if machine is in a grounded state
     fn is_grounded(&self) -> bool;
 
+    /// Check if machine is in a swimming state
+    fn is_swimming(&self) -> bool;
+
     /// Obtain the movement stats of a movement state
     fn movement_stats(&self) -> MovementStats;
 
@@ -79,89 +248,204 @@ pub trait PlayerStateMachine {
     fn jump_strength(&self) -> f32;
 }
 
+#[derive(Clone, Copy, Debug, Reflect, Deserialize)]
 pub struct MovementStats {
     /// Maximum obtainable speed
     pub max_speed: f32,
     /// Constant acceleration on that state
     pub acceleration: f32,
+    /// Constant deceleration when slowing down on that state
+    pub deceleration: f32,
     /// When the player can only steer the movement use this value
     pub rotation_rate: f32,
 }
 
-const MAX_JUMP_LENGTH: f32 = 0.2;
-const MAX_CROUCH_JUMP_LENGTH: f32 = 0.3;
-const MAX_DIVE_JUMP_LENGTH: f32 = 0.1;
+impl MovementStats {
+    /// Scale the stats by the properties of the surface being stood on
+    pub fn on_surface(self, surface: &SurfaceProperties) -> Self {
+        Self {
+            max_speed: self.max_speed,
+            acceleration: self.acceleration * surface.acceleration,
+            deceleration: self.deceleration * surface.friction,
+            rotation_rate: self.rotation_rate * surface.friction,
+        }
+    }
+}
 
 impl PlayerStateMachine for StateMachine {
-    fn jump(&mut self) -> Result<MajorMoveState, MajorMoveState> {
-        match &self.movement_state {
+    fn jump(&mut self, air_jump: bool, speed: f32) -> Result<MajorMoveState, TransitionError> {
+        let jump_type = match &self.movement_state {
             MajorMoveState::Grounded(substate) => match substate {
-                MinorGroundState::Moving => {
-                    return self.transition(MajorMoveState::Airborne(MinorAirborneState::Jumping(
-                        JumpType::Normal(MAX_JUMP_LENGTH),
-                    )));
-                }
-                MinorGroundState::Crouched | MinorGroundState::Sliding => {
-                    return self.transition(MajorMoveState::Airborne(MinorAirborneState::Jumping(
-                        JumpType::Crouch(MAX_CROUCH_JUMP_LENGTH),
-                    )));
+                MinorGroundState::Moving => JumpType::Normal(self.tuning.normal_jump.length),
+                MinorGroundState::Crouched => JumpType::Crouch(self.tuning.crouch_jump.length),
+                MinorGroundState::Sliding if speed >= self.tuning.long_jump.min_speed => {
+                    JumpType::Long(self.tuning.long_jump.length)
                 }
+                MinorGroundState::Sliding => JumpType::Crouch(self.tuning.crouch_jump.length),
             },
             MajorMoveState::Airborne(substate) => match substate {
-                MinorAirborneState::Dive => {
-                    return self.transition(MajorMoveState::Airborne(MinorAirborneState::Jumping(
-                        JumpType::Dive(MAX_DIVE_JUMP_LENGTH),
-                    )));
-                }
-                MinorAirborneState::Jumping(jump_type) => {
-                    return Ok(MajorMoveState::Airborne(MinorAirborneState::Jumping(
-                        jump_type.clone(),
-                    )));
-                }
-                MinorAirborneState::Falling | MinorAirborneState::Glide => {
-                    if self.coyote_timer > f32::EPSILON {
-                        if let Ok(_) = self.transition(MajorMoveState::Airborne(
-                            MinorAirborneState::Jumping(JumpType::Normal(MAX_JUMP_LENGTH)),
-                        )) {
-                            self.coyote_timer = 0.0;
-                            return Ok(self.movement_state.clone());
-                        }
-                    }
-                }
+                MinorAirborneState::Dive => JumpType::Dive(self.tuning.dive_jump.length),
+                // Already jumping, keep the jump going
+                MinorAirborneState::Jumping(_) => return Ok(self.movement_state.clone()),
+                MinorAirborneState::Falling
+                | MinorAirborneState::Glide
+                | MinorAirborneState::Swinging
+                | MinorAirborneState::Dash(_) => JumpType::Normal(self.tuning.normal_jump.length),
             },
+            MajorMoveState::Swimming(_) => JumpType::Normal(self.tuning.normal_jump.length),
+        };
+
+        let mut candidates = vec![MajorMoveState::Airborne(MinorAirborneState::Jumping(
+            jump_type,
+        ))];
+        if air_jump {
+            candidates.push(MajorMoveState::Airborne(MinorAirborneState::Jumping(
+                JumpType::Double(self.tuning.double_jump.length),
+            )));
         }
 
-        return Err(self.movement_state.clone());
+        self.transition_by_priority(candidates)
     }
 
     fn tick(&mut self, time: Time) -> () {
         let delta = time.delta_secs();
 
+        self.ticks += 1;
+
         self.coyote_timer -= delta;
         self.coyote_timer = self.coyote_timer.max(0.0);
 
         self.stuck_in_state_timer -= delta;
         self.stuck_in_state_timer = self.stuck_in_state_timer.max(0.0);
 
+        self.jump_buffer_timer -= delta;
+        self.jump_buffer_timer = self.jump_buffer_timer.max(0.0);
+
+        self.dive_buffer_timer -= delta;
+        self.dive_buffer_timer = self.dive_buffer_timer.max(0.0);
+
         match self.movement_state {
+            // A dive press only carries over within the air, or a crouch would turn the next jump into a dive
             MajorMoveState::Grounded(_) => {
-                self.coyote_timer = 0.25;
-                self.can_dive = true;
+                self.coyote_timer = self.tuning.coyote_time;
+                self.dive_buffer_timer = 0.0;
+                self.refill_air_actions();
+            }
+            MajorMoveState::Swimming(_) => {
+                self.coyote_timer = 0.0;
+                self.dive_buffer_timer = 0.0;
+                self.refill_air_actions();
             }
             MajorMoveState::Airborne(_) => {}
         }
     }
 
-    fn transition(&mut self, new_state: MajorMoveState) -> Result<MajorMoveState, MajorMoveState> {
-        if self.stuck_in_state_timer > 0.0 {
-            return Err(new_state);
+    fn buffer_jump(&mut self) {
+        self.jump_buffer_timer = self.tuning.jump_buffer_time;
+    }
+
+    fn buffer_dive(&mut self) {
+        self.dive_buffer_timer = self.tuning.dive_buffer_time;
+    }
+
+    fn jump_buffered(&self) -> bool {
+        self.jump_buffer_timer > 0.0
+    }
+
+    fn dive_buffered(&self) -> bool {
+        self.dive_buffer_timer > 0.0
+    }
+
+    fn clear_jump_buffer(&mut self) {
+        self.jump_buffer_timer = 0.0;
+    }
+
+    fn clear_dive_buffer(&mut self) {
+        self.dive_buffer_timer = 0.0;
+    }
+
+    fn use_buffered_dive(&mut self) -> Option<MajorMoveState> {
+        let in_air = matches!(self.movement_state, MajorMoveState::Airborne(_));
+        let diving = matches!(
+            self.movement_state,
+            MajorMoveState::Airborne(MinorAirborneState::Dive)
+        );
+        if !in_air || diving || !self.dive_buffered() {
+            return None;
         }
 
-        self.movement_state = new_state;
+        let new_state = self
+            .transition(MajorMoveState::Airborne(MinorAirborneState::Dive))
+            .ok()?;
+        self.clear_dive_buffer();
+        Some(new_state)
+    }
+
+    fn transition(&mut self, new_state: MajorMoveState) -> Result<MajorMoveState, TransitionError> {
+        check_transition(self, new_state.kind(), false)?;
+
+        self.set_state(new_state);
+
+        Ok(self.movement_state.clone())
+    }
+
+    fn transition_by_priority(
+        &mut self,
+        mut candidates: Vec<MajorMoveState>,
+    ) -> Result<MajorMoveState, TransitionError> {
+        let kinds: Vec<_> = candidates.iter().map(MajorMoveState::kind).collect();
+        let index = resolve_transition(self, &kinds)?;
+
+        self.set_state(candidates.swap_remove(index));
+
+        Ok(self.movement_state.clone())
+    }
+
+    fn force_transition(
+        &mut self,
+        new_state: MajorMoveState,
+    ) -> Result<MajorMoveState, TransitionError> {
+        check_transition(self, new_state.kind(), true)?;
+
+        self.coyote_timer = 0.0;
+
+        self.set_state(new_state);
 
         Ok(self.movement_state.clone())
     }
 
+    fn on_enter(&mut self, _from: &MajorMoveState) {
+        match &self.movement_state {
+            MajorMoveState::Airborne(MinorAirborneState::Jumping(jump_type)) => {
+                self.coyote_timer = 0.0;
+
+                if let JumpType::Double(_) = jump_type {
+                    AirActions::consume(&mut self.air_actions.jumps);
+                }
+            }
+            MajorMoveState::Airborne(MinorAirborneState::Dive) => {
+                AirActions::consume(&mut self.air_actions.dives);
+            }
+            MajorMoveState::Airborne(MinorAirborneState::Glide) => {
+                AirActions::consume(&mut self.air_actions.glides);
+            }
+            // Only a press made while swinging jumps off the rope
+            MajorMoveState::Airborne(MinorAirborneState::Swinging) => {
+                self.jump_buffer_timer = 0.0;
+            }
+            MajorMoveState::Airborne(MinorAirborneState::Dash(_)) => {
+                AirActions::consume(&mut self.air_actions.dashes);
+                self.stuck_in_state_timer = self.tuning.dash.duration;
+            }
+            _ => {}
+        }
+    }
+
+    fn on_exit(&mut self, _to: &MajorMoveState) {
+        // Being stuck only applies to the state that asked for it
+        self.stuck_in_state_timer = 0.0;
+    }
+
     fn set_y_0(&self) -> bool {
         match &self.movement_state {
             MajorMoveState::Grounded(substate) => match substate {
@@ -170,91 +454,42 @@ impl PlayerStateMachine for StateMachine {
                 MinorGroundState::Crouched => false,
             },
             MajorMoveState::Airborne(_) => false,
+            MajorMoveState::Swimming(_) => false,
         }
     }
 
     fn is_grounded(&self) -> bool {
         match self.movement_state {
             MajorMoveState::Grounded(_) => true,
-            MajorMoveState::Airborne(_) => false,
+            MajorMoveState::Airborne(_) | MajorMoveState::Swimming(_) => false,
         }
     }
 
-    fn movement_stats(&self) -> MovementStats {
-        match &self.movement_state {
-            MajorMoveState::Grounded(substate) => match substate {
-                MinorGroundState::Moving => {
-                    return MovementStats {
-                        max_speed: 10.0,
-                        acceleration: 30.0,
-                        rotation_rate: 15.0,
-                    };
-                }
-                MinorGroundState::Sliding => {
-                    return MovementStats {
-                        max_speed: 0.0,
-                        acceleration: 0.0,
-                        rotation_rate: 20.0,
-                    };
-                }
-                MinorGroundState::Crouched => {
-                    return MovementStats {
-                        max_speed: 0.0,
-                        acceleration: 10.0,
-                        rotation_rate: 10.0,
-                    };
-                }
-            },
-            MajorMoveState::Airborne(substate) => match substate {
-                MinorAirborneState::Falling | MinorAirborneState::Jumping(_) => {
-                    return MovementStats {
-                        max_speed: 10.0,
-                        acceleration: 10.0,
-                        rotation_rate: 10.0,
-                    };
-                }
-                MinorAirborneState::Glide => {
-                    return MovementStats {
-                        max_speed: 5.0,
-                        acceleration: 10.0,
-                        rotation_rate: 20.0,
-                    };
-                }
-                MinorAirborneState::Dive => {
-                    return MovementStats {
-                        max_speed: 10.0,
-                        acceleration: 5.0,
-                        rotation_rate: 0.0,
-                    };
-                }
-            },
+    fn is_swimming(&self) -> bool {
+        match self.movement_state {
+            MajorMoveState::Swimming(_) => true,
+            MajorMoveState::Grounded(_) | MajorMoveState::Airborne(_) => false,
         }
     }
 
+    fn movement_stats(&self) -> MovementStats {
+        self.state_tuning().stats
+    }
+
     fn gravity(&self) -> (f32, f32, f32) {
-        match &self.movement_state {
-            MajorMoveState::Grounded(substate) => match substate {
-                MinorGroundState::Moving | MinorGroundState::Crouched => return (0.0, 0.0, 0.0),
-                MinorGroundState::Sliding => return (60.0, 60.0, f32::INFINITY),
-            },
-            MajorMoveState::Airborne(substate) => match substate {
-                MinorAirborneState::Jumping(_) => {
-                    return (0.0, 0.0, 1.0);
-                }
-                MinorAirborneState::Falling => return (15.0, 25.0, 20.0),
-                MinorAirborneState::Glide => return (1.0, 1.0, 5.0),
-                MinorAirborneState::Dive => return (4.0, 160.0, 80.0),
-            },
-        }
+        let gravity = self.state_tuning().gravity;
+        (gravity.up, gravity.down, gravity.terminal_velocity)
     }
 
     fn jump_strength(&self) -> f32 {
         match &self.movement_state {
             MajorMoveState::Airborne(substate) => match substate {
                 MinorAirborneState::Jumping(jump_type) => match jump_type {
-                    JumpType::Normal(_) => return 5.0,
-                    JumpType::Crouch(_) => return 7.0,
-                    JumpType::Dive(_) => return 7.0,
+                    JumpType::Normal(_) => return self.tuning.normal_jump.strength,
+                    JumpType::Crouch(_) => return self.tuning.crouch_jump.strength,
+                    JumpType::Dive(_) => return self.tuning.dive_jump.strength,
+                    JumpType::Double(_) => return self.tuning.double_jump.strength,
+                    JumpType::Long(_) => return self.tuning.long_jump.strength,
                 },
                 _ => {}
             },
@@ -263,3 +498,260 @@ impl PlayerStateMachine for StateMachine {
         return 0.0;
     }
 }
+
+impl StateMachine {
+    /// Time left to jump after walking off a ledge
+    pub fn coyote_timer(&self) -> f32 {
+        self.coyote_timer
+    }
+
+    /// Give back every air action, up to the pickup limit if there is one
+    pub fn refill_air_actions(&mut self) {
+        self.air_actions = self.air_action_limit.unwrap_or(self.tuning.air_actions);
+    }
+
+    /// Last transitions, oldest first
+    pub fn history(&self) -> impl Iterator<Item = &StateChange> {
+        self.history.iter()
+    }
+
+    /// Change the state running the hooks and recording it, does nothing if the state is the same
+    fn set_state(&mut self, new_state: MajorMoveState) {
+        if self.movement_state == new_state {
+            return;
+        }
+
+        self.on_exit(&new_state);
+        let old_state = std::mem::replace(&mut self.movement_state, new_state);
+        self.on_enter(&old_state);
+
+        if self.history.len() >= HISTORY_LENGTH {
+            self.history.pop_front();
+        }
+        self.history.push_back(StateChange {
+            from: old_state,
+            to: self.movement_state.clone(),
+            tick: self.ticks,
+        });
+        self.unreported_changes = (self.unreported_changes + 1).min(HISTORY_LENGTH);
+    }
+
+    /// Tuning entry of the current movement state
+    fn state_tuning(&self) -> &StateTuning {
+        let tuning = &self.tuning;
+        match &self.movement_state {
+            MajorMoveState::Grounded(substate) => match substate {
+                MinorGroundState::Moving => &tuning.moving,
+                MinorGroundState::Sliding => &tuning.sliding,
+                MinorGroundState::Crouched => &tuning.crouched,
+            },
+            MajorMoveState::Airborne(substate) => match substate {
+                MinorAirborneState::Falling => &tuning.falling,
+                MinorAirborneState::Jumping(_) => &tuning.jumping,
+                MinorAirborneState::Dive => &tuning.dive,
+                MinorAirborneState::Glide => &tuning.glide,
+                MinorAirborneState::Swinging => &tuning.swinging,
+                MinorAirborneState::Dash(_) => &tuning.dashing,
+            },
+            MajorMoveState::Swimming(substate) => match substate {
+                MinorSwimState::Surface => &tuning.swimming_surface,
+                MinorSwimState::Underwater => &tuning.swimming_underwater,
+            },
+        }
+    }
+}
+
+pub(super) fn emit_state_changes(
+    mut commands: Commands,
+    machines: Query<(Entity, &mut StateMachine)>,
+    mut messages: MessageWriter<MoveStateChanged>,
+) {
+    for (entity, mut machine) in machines {
+        if machine.unreported_changes == 0 {
+            continue;
+        }
+
+        let first_unreported = machine.history.len() - machine.unreported_changes;
+        machine.unreported_changes = 0;
+
+        for change in machine.history.iter().skip(first_unreported) {
+            commands.trigger(MoveStateExited {
+                entity,
+                state: change.from.clone(),
+                to: change.to.clone(),
+            });
+            commands.trigger(MoveStateEntered {
+                entity,
+                state: change.to.clone(),
+                from: change.from.clone(),
+            });
+            messages.write(MoveStateChanged {
+                entity,
+                from: change.from.clone(),
+                to: change.to.clone(),
+                tick: change.tick,
+            });
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::time::Duration;
+
+    /// Length of a fixed update tick
+    const TICK: f32 = 1.0 / 64.0;
+
+    fn airborne(state: MinorAirborneState) -> StateMachine {
+        let mut machine = StateMachine {
+            movement_state: MajorMoveState::Airborne(state),
+            ..default()
+        };
+        machine.refill_air_actions();
+        machine
+    }
+
+    fn run_ticks(machine: &mut StateMachine, ticks: usize) {
+        for _ in 0..ticks {
+            let mut time = Time::<()>::default();
+            time.advance_by(Duration::from_secs_f32(TICK));
+            machine.tick(time);
+        }
+    }
+
+    /// Ticks that stay inside a buffer window and ticks that go past it
+    fn ticks_around(window: f32) -> (usize, usize) {
+        ((window * 0.5 / TICK) as usize, (window / TICK) as usize + 1)
+    }
+
+    fn land(machine: &mut StateMachine) {
+        machine
+            .transition(MajorMoveState::Grounded(MinorGroundState::Moving))
+            .unwrap();
+    }
+
+    #[test]
+    fn jump_pressed_before_landing_is_used_on_landing() {
+        let mut machine = airborne(MinorAirborneState::Falling);
+        let (inside, _) = ticks_around(machine.tuning.jump_buffer_time);
+
+        machine.buffer_jump();
+        run_ticks(&mut machine, inside);
+        land(&mut machine);
+
+        assert!(machine.jump_buffered());
+        assert!(matches!(
+            machine.jump(false, 0.0),
+            Ok(MajorMoveState::Airborne(MinorAirborneState::Jumping(
+                JumpType::Normal(_)
+            )))
+        ));
+    }
+
+    #[test]
+    fn old_jump_press_is_dropped() {
+        let mut machine = airborne(MinorAirborneState::Falling);
+        let (_, past) = ticks_around(machine.tuning.jump_buffer_time);
+
+        machine.buffer_jump();
+        run_ticks(&mut machine, past);
+        land(&mut machine);
+
+        assert!(!machine.jump_buffered());
+    }
+
+    #[test]
+    fn dive_pressed_early_is_used_in_the_air() {
+        let mut machine = airborne(MinorAirborneState::Jumping(JumpType::Normal(0.0)));
+        let (inside, _) = ticks_around(machine.tuning.dive_buffer_time);
+
+        let dives = machine.air_actions.dives;
+
+        machine.buffer_dive();
+        run_ticks(&mut machine, inside);
+
+        assert_eq!(
+            machine.use_buffered_dive(),
+            Some(MajorMoveState::Airborne(MinorAirborneState::Dive))
+        );
+        assert_eq!(
+            machine.movement_state,
+            MajorMoveState::Airborne(MinorAirborneState::Dive)
+        );
+        assert!(!machine.dive_buffered());
+        assert_eq!(machine.air_actions.dives, dives - 1);
+    }
+
+    #[test]
+    fn old_dive_press_is_dropped() {
+        let mut machine = airborne(MinorAirborneState::Jumping(JumpType::Normal(0.0)));
+        let (_, past) = ticks_around(machine.tuning.dive_buffer_time);
+
+        machine.buffer_dive();
+        run_ticks(&mut machine, past);
+
+        assert!(!machine.dive_buffered());
+        assert_eq!(machine.use_buffered_dive(), None);
+    }
+
+    #[test]
+    fn dive_press_does_not_carry_over_the_ground() {
+        let mut machine = airborne(MinorAirborneState::Falling);
+
+        machine.buffer_dive();
+        land(&mut machine);
+        run_ticks(&mut machine, 1);
+
+        assert!(!machine.dive_buffered());
+    }
+
+    #[test]
+    fn slow_slide_jump_is_a_crouch_jump() {
+        let mut machine = StateMachine {
+            movement_state: MajorMoveState::Grounded(MinorGroundState::Sliding),
+            ..default()
+        };
+        let speed = machine.tuning.long_jump.min_speed - 1.0;
+
+        assert!(matches!(
+            machine.jump(false, speed),
+            Ok(MajorMoveState::Airborne(MinorAirborneState::Jumping(
+                JumpType::Crouch(_)
+            )))
+        ));
+    }
+
+    #[test]
+    fn fast_slide_jump_is_a_long_jump() {
+        let mut machine = StateMachine {
+            movement_state: MajorMoveState::Grounded(MinorGroundState::Sliding),
+            ..default()
+        };
+        let speed = machine.tuning.long_jump.min_speed;
+
+        assert!(matches!(
+            machine.jump(false, speed),
+            Ok(MajorMoveState::Airborne(MinorAirborneState::Jumping(
+                JumpType::Long(_)
+            )))
+        ));
+    }
+
+    #[test]
+    fn jump_during_dive_is_a_dive_jump() {
+        let mut machine = airborne(MinorAirborneState::Dive);
+
+        machine.buffer_jump();
+        run_ticks(&mut machine, 1);
+
+        assert!(machine.jump_buffered());
+        assert!(matches!(
+            machine.jump(false, 0.0),
+            Ok(MajorMoveState::Airborne(MinorAirborneState::Jumping(
+                JumpType::Dive(_)
+            )))
+        ));
+    }
+}
@@ -0,0 +1,166 @@
+use super::state_machine::*;
+use super::{PLAYER_HEIGHT, PlayerLookDirection};
+use crate::character_body::{CharacterBody, CharacterContacts};
+use crate::input::PlayerInput;
+
+use avian3d::prelude::*;
+use bevy::prelude::*;
+use leafwing_input_manager::prelude::*;
+
+pub(super) struct SwimmingPlugin;
+impl Plugin for SwimmingPlugin {
+    fn build(&self, app: &mut App) {
+        app.register_type::<WaterVolume>()
+            .register_type::<Submersion>();
+    }
+}
+
+/// Body of water the player can swim in. The top of its collider is the water surface
+#[derive(Component, Reflect, Clone, Copy, Debug)]
+#[require(Sensor, ColliderConstructor::ConvexHullFromMesh)]
+#[reflect(Component, Default)]
+pub struct WaterVolume {
+    /// Upwards acceleration when fully submerged
+    pub buoyancy: f32,
+    /// How fast velocity is damped inside the water
+    pub drag: f32,
+    /// Color of the fog when the camera is underwater
+    pub fog_color: Color,
+}
+
+impl Default for WaterVolume {
+    fn default() -> Self {
+        Self {
+            buoyancy: 15.0,
+            drag: 2.0,
+            fog_color: Color::srgb(0.05, 0.25, 0.35),
+        }
+    }
+}
+
+/// How deep the player is in the water it overlaps
+#[derive(Component, Reflect, Clone, Copy, Default, Debug)]
+#[reflect(Component)]
+pub struct Submersion {
+    /// Distance from the feet of the player to the water surface, 0.0 out of water
+    pub depth: f32,
+    pub buoyancy: f32,
+    pub drag: f32,
+}
+
+/// Depth at which the player starts swimming
+const SWIM_DEPTH: f32 = PLAYER_HEIGHT * 0.5;
+/// Depth at which the player stops swimming
+const STOP_SWIM_DEPTH: f32 = PLAYER_HEIGHT * 0.25;
+/// Depth at which the head of the player goes underwater
+const UNDERWATER_DEPTH: f32 = PLAYER_HEIGHT * 0.9;
+
+pub(super) fn player_detect_water(
+    players: Query<(
+        &mut StateMachine,
+        &mut Submersion,
+        &Transform,
+        &CharacterContacts,
+        &CharacterBody,
+        &LinearVelocity,
+    )>,
+    waters: Query<(&WaterVolume, &ColliderAabb)>,
+) {
+    for (mut state, mut submersion, transform, contacts, body, velocity) in players {
+        // Depths are measured along the up of the player so water works in gravity zones too
+        let up = *body.up;
+        let feet = transform.translation.dot(up) - PLAYER_HEIGHT / 2.0;
+
+        *submersion = Submersion::default();
+        for (water, aabb) in contacts.iter().filter_map(|entity| waters.get(entity).ok()) {
+            let depth = surface_height(aabb, up) - feet;
+
+            if depth > submersion.depth {
+                *submersion = Submersion {
+                    depth,
+                    buoyancy: water.buoyancy,
+                    drag: water.drag,
+                };
+            }
+        }
+
+        let swim_state = if submersion.depth > UNDERWATER_DEPTH {
+            MinorSwimState::Underwater
+        } else {
+            MinorSwimState::Surface
+        };
+
+        match &state.movement_state {
+            MajorMoveState::Swimming(_) => {
+                if submersion.depth < STOP_SWIM_DEPTH {
+                    let _ = if body.grounded {
+                        state.transition(MajorMoveState::Grounded(MinorGroundState::Moving))
+                    } else {
+                        state.transition(MajorMoveState::Airborne(MinorAirborneState::Falling))
+                    };
+                } else {
+                    let _ = state.transition(MajorMoveState::Swimming(swim_state));
+                }
+            }
+            // Don't pull the player back in while jumping out
+            MajorMoveState::Airborne(MinorAirborneState::Jumping(_)) => {}
+            _ => {
+                if submersion.depth > SWIM_DEPTH && body.vertical_speed(velocity.0) <= 0.0 {
+                    let _ = state.transition(MajorMoveState::Swimming(swim_state));
+                }
+            }
+        }
+    }
+}
+
+/// Highest point of the box along the up direction, the water surface for that up
+fn surface_height(aabb: &ColliderAabb, up: Vec3) -> f32 {
+    (aabb.min * up).max(aabb.max * up).element_sum()
+}
+
+pub(super) fn player_swim(
+    players: Query<(
+        &mut LinearVelocity,
+        &StateMachine,
+        &Submersion,
+        &CharacterBody,
+        &ActionState<PlayerInput>,
+        &PlayerLookDirection,
+    )>,
+    time: Res<Time>,
+) {
+    for (mut velocity, state, submersion, body, input, look_direction) in players {
+        let MajorMoveState::Swimming(substate) = &state.movement_state else {
+            continue;
+        };
+
+        let movement_stats = state.movement_stats();
+
+        let up = *body.up;
+        let look = look_direction.0.normalize_or(Vec3::NEG_Z);
+        let flat_forward = body.horizontal(look);
+        let mut rise = look.dot(up);
+        // Only dive from the surface, don't fly out of the water
+        if let MinorSwimState::Surface = substate {
+            rise = rise.min(0.0);
+        }
+        let forward = flat_forward + up * rise;
+        let right = flat_forward.normalize_or_zero().cross(up);
+
+        let input_axis = input.axis_pair(&PlayerInput::Move);
+        let input_direction = (forward * input_axis.y + right * input_axis.x).clamp_length_max(1.0);
+
+        let old_speed = velocity.length();
+        velocity.0 += input_direction * movement_stats.acceleration * time.delta_secs();
+        velocity.0 = velocity
+            .0
+            .clamp_length_max(old_speed.max(movement_stats.max_speed));
+
+        let submerged_fraction = (submersion.depth / PLAYER_HEIGHT).clamp(0.0, 1.0);
+        let vertical_speed = body.vertical_speed(velocity.0)
+            + submersion.buoyancy * submerged_fraction * time.delta_secs();
+        velocity.0 = body.with_vertical_speed(velocity.0, vertical_speed);
+
+        velocity.0 *= (-submersion.drag * time.delta_secs()).exp();
+    }
+}
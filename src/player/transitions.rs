@@ -0,0 +1,489 @@
+use super::state_machine::*;
+
+use bevy::prelude::*;
+use serde::Deserialize;
+
+use std::collections::HashSet;
+use std::fmt;
+
+use MoveStateKind::*;
+use TransitionGuard::*;
+
+/// Movement state without its data, used to declare transitions
+#[derive(Reflect, Deserialize, Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum MoveStateKind {
+    Moving,
+    Sliding,
+    Crouched,
+    Falling,
+    JumpNormal,
+    JumpCrouch,
+    JumpDive,
+    JumpDouble,
+    JumpLong,
+    Dive,
+    Glide,
+    Swinging,
+    Dash,
+    SwimSurface,
+    SwimUnderwater,
+}
+
+impl MoveStateKind {
+    pub const ALL: &[MoveStateKind] = &[
+        MoveStateKind::Moving,
+        MoveStateKind::Sliding,
+        MoveStateKind::Crouched,
+        MoveStateKind::Falling,
+        MoveStateKind::JumpNormal,
+        MoveStateKind::JumpCrouch,
+        MoveStateKind::JumpDive,
+        MoveStateKind::JumpDouble,
+        MoveStateKind::JumpLong,
+        MoveStateKind::Dive,
+        MoveStateKind::Glide,
+        MoveStateKind::Swinging,
+        MoveStateKind::Dash,
+        MoveStateKind::SwimSurface,
+        MoveStateKind::SwimUnderwater,
+    ];
+}
+
+impl MajorMoveState {
+    pub fn kind(&self) -> MoveStateKind {
+        match self {
+            MajorMoveState::Grounded(substate) => match substate {
+                MinorGroundState::Moving => MoveStateKind::Moving,
+                MinorGroundState::Sliding => MoveStateKind::Sliding,
+                MinorGroundState::Crouched => MoveStateKind::Crouched,
+            },
+            MajorMoveState::Airborne(substate) => match substate {
+                MinorAirborneState::Falling => MoveStateKind::Falling,
+                MinorAirborneState::Jumping(jump_type) => match jump_type {
+                    JumpType::Normal(_) => MoveStateKind::JumpNormal,
+                    JumpType::Crouch(_) => MoveStateKind::JumpCrouch,
+                    JumpType::Dive(_) => MoveStateKind::JumpDive,
+                    JumpType::Double(_) => MoveStateKind::JumpDouble,
+                    JumpType::Long(_) => MoveStateKind::JumpLong,
+                },
+                MinorAirborneState::Dive => MoveStateKind::Dive,
+                MinorAirborneState::Glide => MoveStateKind::Glide,
+                MinorAirborneState::Swinging => MoveStateKind::Swinging,
+                MinorAirborneState::Dash(_) => MoveStateKind::Dash,
+            },
+            MajorMoveState::Swimming(substate) => match substate {
+                MinorSwimState::Surface => MoveStateKind::SwimSurface,
+                MinorSwimState::Underwater => MoveStateKind::SwimUnderwater,
+            },
+        }
+    }
+}
+
+/// Condition that has to pass for a transition to happen
+#[derive(Reflect, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TransitionGuard {
+    /// Ignores the stuck timer
+    Always,
+    /// The stuck timer ran out
+    NotStuck,
+    /// Not stuck and there is a dive left
+    CanDive,
+    /// Not stuck and there is a glide left
+    CanGlide,
+    /// Not stuck and there is an air jump left
+    CanAirJump,
+    /// Not stuck and there is a dash left
+    CanDash,
+    /// Ignores the stuck timer if there is a new jump press
+    FreshJump,
+    /// Ignores the stuck timer if there is a new dive press and a dive left
+    FreshDive,
+    /// Not stuck and still within coyote time
+    CoyoteTime,
+}
+
+impl TransitionGuard {
+    pub fn passes(&self, machine: &StateMachine) -> bool {
+        let not_stuck = machine.stuck_in_state_timer <= 0.0;
+
+        match self {
+            TransitionGuard::Always => true,
+            TransitionGuard::NotStuck => not_stuck,
+            TransitionGuard::CanDive => not_stuck && machine.air_actions.dives > 0,
+            TransitionGuard::CanGlide => not_stuck && machine.air_actions.glides > 0,
+            TransitionGuard::CanAirJump => not_stuck && machine.air_actions.jumps > 0,
+            TransitionGuard::CanDash => not_stuck && machine.air_actions.dashes > 0,
+            TransitionGuard::FreshJump => machine.jump_buffered(),
+            TransitionGuard::FreshDive => machine.dive_buffered() && machine.air_actions.dives > 0,
+            TransitionGuard::CoyoteTime => not_stuck && machine.coyote_timer() > f32::EPSILON,
+        }
+    }
+}
+
+/// A transition has at most one rule, conditions that combine go in a single guard
+pub struct TransitionRule {
+    pub from: &'static [MoveStateKind],
+    pub to: MoveStateKind,
+    pub guard: TransitionGuard,
+    /// When several states could be entered at once, the passing rule with the highest priority wins
+    pub priority: u8,
+}
+
+const fn rule(
+    from: &'static [MoveStateKind],
+    to: MoveStateKind,
+    guard: TransitionGuard,
+) -> TransitionRule {
+    TransitionRule {
+        from,
+        to,
+        guard,
+        priority: 0,
+    }
+}
+
+impl TransitionRule {
+    const fn with_priority(mut self, priority: u8) -> Self {
+        self.priority = priority;
+        self
+    }
+}
+
+const GROUNDED: &[MoveStateKind] = &[Moving, Sliding, Crouched];
+const JUMPS: &[MoveStateKind] = &[JumpNormal, JumpCrouch, JumpDive, JumpDouble, JumpLong];
+const AIRBORNE: &[MoveStateKind] = &[
+    Falling, JumpNormal, JumpCrouch, JumpDive, JumpDouble, JumpLong, Dive, Glide, Swinging, Dash,
+];
+const SWIMMING: &[MoveStateKind] = &[SwimSurface, SwimUnderwater];
+
+/// Every legal transition of the player state machine
+pub const TRANSITIONS: &[TransitionRule] = &[
+    // Grounded
+    rule(&[Moving, Crouched], Sliding, NotStuck),
+    rule(&[Moving], Crouched, NotStuck),
+    rule(&[Sliding, Crouched], Moving, NotStuck),
+    rule(GROUNDED, Falling, NotStuck),
+    rule(&[Moving], JumpNormal, NotStuck),
+    rule(&[Crouched, Sliding], JumpCrouch, NotStuck),
+    rule(&[Sliding], JumpLong, NotStuck),
+    rule(GROUNDED, Swinging, NotStuck),
+    rule(GROUNDED, SwimSurface, NotStuck),
+    rule(GROUNDED, SwimUnderwater, NotStuck),
+    // Airborne
+    rule(AIRBORNE, Moving, NotStuck),
+    rule(JUMPS, Falling, NotStuck),
+    rule(&[Dive, Glide, Swinging, Dash], Falling, NotStuck),
+    rule(JUMPS, Dive, CanDive),
+    rule(&[Falling, Glide, Swinging], Dive, CanDive),
+    rule(&[Dive], JumpDive, NotStuck),
+    // A coyote jump doesn't use up the air jump
+    rule(&[Falling, Glide], JumpNormal, CoyoteTime).with_priority(1),
+    rule(&[Swinging], JumpNormal, NotStuck),
+    rule(&[Falling, Glide], JumpDouble, CanAirJump),
+    rule(JUMPS, Glide, CanGlide),
+    rule(&[Falling], Glide, CanGlide),
+    rule(JUMPS, Swinging, NotStuck),
+    rule(&[Falling, Dive, Glide, Dash], Swinging, NotStuck),
+    rule(JUMPS, Dash, CanDash),
+    rule(&[Falling, Glide], Dash, CanDash),
+    // Dash cancels
+    rule(&[Dash], JumpNormal, FreshJump),
+    rule(&[Dash], Dive, FreshDive),
+    rule(
+        &[Falling, Dive, Glide, Swinging, Dash],
+        SwimSurface,
+        NotStuck,
+    ),
+    rule(
+        &[Falling, Dive, Glide, Swinging, Dash],
+        SwimUnderwater,
+        NotStuck,
+    ),
+    // Swimming
+    rule(&[SwimUnderwater], SwimSurface, NotStuck),
+    rule(&[SwimSurface], SwimUnderwater, NotStuck),
+    rule(SWIMMING, Moving, NotStuck),
+    rule(SWIMMING, Falling, NotStuck),
+    rule(&[SwimSurface], JumpNormal, NotStuck),
+];
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum TransitionError {
+    /// No rule allows this transition
+    Illegal {
+        from: MoveStateKind,
+        to: MoveStateKind,
+    },
+    /// The transition is allowed but its guard didn't pass
+    Blocked {
+        from: MoveStateKind,
+        to: MoveStateKind,
+        guard: TransitionGuard,
+    },
+    /// There were no states to pick from
+    NoCandidates { from: MoveStateKind },
+}
+
+impl fmt::Display for TransitionError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TransitionError::Illegal { from, to } => {
+                write!(f, "no transition from {from:?} to {to:?}")
+            }
+            TransitionError::Blocked { from, to, guard } => {
+                write!(f, "transition from {from:?} to {to:?} blocked by {guard:?}")
+            }
+            TransitionError::NoCandidates { from } => {
+                write!(f, "no states to transition to from {from:?}")
+            }
+        }
+    }
+}
+
+impl std::error::Error for TransitionError {}
+
+fn rule_between(from: MoveStateKind, to: MoveStateKind) -> Option<&'static TransitionRule> {
+    TRANSITIONS
+        .iter()
+        .find(|rule| rule.to == to && rule.from.contains(&from))
+}
+
+/// Check if the machine can go to the new state, ignoring guards if `force`
+pub fn check_transition(
+    machine: &StateMachine,
+    to: MoveStateKind,
+    force: bool,
+) -> Result<(), TransitionError> {
+    let from = machine.movement_state.kind();
+
+    // Staying in the same state only updates its data
+    if from == to {
+        return if force || NotStuck.passes(machine) {
+            Ok(())
+        } else {
+            Err(TransitionError::Blocked {
+                from,
+                to,
+                guard: NotStuck,
+            })
+        };
+    }
+
+    let Some(rule) = rule_between(from, to) else {
+        return Err(TransitionError::Illegal { from, to });
+    };
+
+    if force || rule.guard.passes(machine) {
+        Ok(())
+    } else {
+        Err(TransitionError::Blocked {
+            from,
+            to,
+            guard: rule.guard,
+        })
+    }
+}
+
+/// Pick the candidate whose rule from the machine's state has the highest priority and a passing
+/// guard, candidates with the same priority are tried in order. Returns the index of the pick, or
+/// the error of the highest priority candidate if none can be entered
+pub fn resolve_transition(
+    machine: &StateMachine,
+    candidates: &[MoveStateKind],
+) -> Result<usize, TransitionError> {
+    let from = machine.movement_state.kind();
+
+    let mut ranked: Vec<(usize, u8)> = candidates
+        .iter()
+        .enumerate()
+        .map(|(index, to)| {
+            let priority = rule_between(from, *to).map_or(0, |rule| rule.priority);
+            (index, priority)
+        })
+        .collect();
+    ranked.sort_by(|a, b| b.1.cmp(&a.1));
+
+    let mut first_error = None;
+    for (index, _) in ranked {
+        match check_transition(machine, candidates[index], false) {
+            Ok(()) => return Ok(index),
+            Err(error) => {
+                first_error.get_or_insert(error);
+            }
+        }
+    }
+
+    Err(first_error.unwrap_or(TransitionError::NoCandidates { from }))
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum TableError {
+    /// Can't get to the state from the default state
+    Unreachable(MoveStateKind),
+    /// There is no way out of the state
+    DeadEnd(MoveStateKind),
+    /// Two rules for the same transition, only the first one would ever be checked
+    ConflictingGuards {
+        from: MoveStateKind,
+        to: MoveStateKind,
+    },
+}
+
+/// Make sure every state can be reached and left and rules don't overlap
+pub fn validate_transitions(rules: &[TransitionRule]) -> Result<(), Vec<TableError>> {
+    let mut errors = Vec::new();
+
+    let start = MajorMoveState::default().kind();
+    let mut reached = HashSet::from([start]);
+    let mut frontier = vec![start];
+    while let Some(from) = frontier.pop() {
+        for rule in rules.iter().filter(|rule| rule.from.contains(&from)) {
+            if reached.insert(rule.to) {
+                frontier.push(rule.to);
+            }
+        }
+    }
+
+    for kind in MoveStateKind::ALL {
+        if !reached.contains(kind) {
+            errors.push(TableError::Unreachable(*kind));
+        }
+
+        if !rules
+            .iter()
+            .any(|rule| rule.from.contains(kind) && rule.to != *kind)
+        {
+            errors.push(TableError::DeadEnd(*kind));
+        }
+    }
+
+    let mut seen = HashSet::new();
+    for rule in rules {
+        for from in rule.from {
+            if !seen.insert((*from, rule.to)) {
+                errors.push(TableError::ConflictingGuards {
+                    from: *from,
+                    to: rule.to,
+                });
+            }
+        }
+    }
+
+    if errors.is_empty() {
+        Ok(())
+    } else {
+        Err(errors)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::time::Duration;
+
+    /// Falling right after walking off a ledge, with coyote time and every air action left
+    fn walked_off_ledge() -> StateMachine {
+        let mut machine = StateMachine::default();
+        machine.tick(Time::default());
+        machine
+            .transition(MajorMoveState::Airborne(MinorAirborneState::Falling))
+            .unwrap();
+        machine
+    }
+
+    #[test]
+    fn transition_table_is_valid() {
+        assert_eq!(validate_transitions(TRANSITIONS), Ok(()));
+    }
+
+    #[test]
+    fn unreachable_state_is_rejected() {
+        let rules = [
+            rule(&[Moving], Falling, NotStuck),
+            rule(&[Falling], Moving, NotStuck),
+            rule(&[Glide], Falling, NotStuck),
+        ];
+
+        let errors = validate_transitions(&rules).unwrap_err();
+        assert!(errors.contains(&TableError::Unreachable(Glide)));
+        assert!(!errors.contains(&TableError::Unreachable(Falling)));
+    }
+
+    #[test]
+    fn dead_end_state_is_rejected() {
+        let rules = [
+            rule(&[Moving], Falling, NotStuck),
+            rule(&[Moving], Sliding, NotStuck),
+            rule(&[Sliding], Moving, NotStuck),
+        ];
+
+        let errors = validate_transitions(&rules).unwrap_err();
+        assert!(errors.contains(&TableError::DeadEnd(Falling)));
+        assert!(!errors.contains(&TableError::DeadEnd(Sliding)));
+    }
+
+    #[test]
+    fn duplicate_guards_are_rejected() {
+        let rules = [
+            rule(&[Moving], Falling, NotStuck),
+            rule(&[Moving, Sliding], Falling, CoyoteTime),
+            rule(&[Falling], Moving, NotStuck),
+        ];
+
+        let errors = validate_transitions(&rules).unwrap_err();
+        assert!(errors.contains(&TableError::ConflictingGuards {
+            from: Moving,
+            to: Falling,
+        }));
+        assert!(!errors.contains(&TableError::ConflictingGuards {
+            from: Sliding,
+            to: Falling,
+        }));
+    }
+
+    #[test]
+    fn higher_priority_rule_wins() {
+        let machine = walked_off_ledge();
+
+        assert_eq!(
+            resolve_transition(&machine, &[JumpDouble, JumpNormal]),
+            Ok(1)
+        );
+    }
+
+    #[test]
+    fn blocked_rule_falls_back_to_the_next_priority() {
+        let mut machine = walked_off_ledge();
+        let mut time = Time::<()>::default();
+        time.advance_by(Duration::from_secs_f32(machine.tuning.coyote_time * 2.0));
+        machine.tick(time);
+
+        assert_eq!(
+            resolve_transition(&machine, &[JumpDouble, JumpNormal]),
+            Ok(0)
+        );
+    }
+
+    #[test]
+    fn no_passing_candidate_gives_the_highest_priority_error() {
+        let mut machine = walked_off_ledge();
+        let mut time = Time::<()>::default();
+        time.advance_by(Duration::from_secs_f32(machine.tuning.coyote_time * 2.0));
+        machine.tick(time);
+        machine.air_actions = AirActions::default();
+
+        assert_eq!(
+            resolve_transition(&machine, &[JumpDouble, JumpNormal]),
+            Err(TransitionError::Blocked {
+                from: Falling,
+                to: JumpNormal,
+                guard: CoyoteTime,
+            })
+        );
+        assert_eq!(
+            resolve_transition(&machine, &[]),
+            Err(TransitionError::NoCandidates { from: Falling })
+        );
+    }
+}
@@ -0,0 +1,179 @@
+use super::state_machine::{AirActions, MovementStats, StateMachine};
+use crate::ron_asset::RonAssetPlugin;
+
+use bevy::prelude::*;
+use serde::Deserialize;
+
+use std::sync::LazyLock;
+
+const MOVEMENT_TUNING_PATH: &str = "player.tuning.ron";
+
+pub(super) struct TuningPlugin;
+impl Plugin for TuningPlugin {
+    fn build(&self, app: &mut App) {
+        app.register_type::<MovementTuning>();
+
+        app.add_plugins(RonAssetPlugin::<MovementTuning>::new(&["tuning.ron"]));
+
+        app.add_systems(Startup, load_movement_tuning);
+        app.add_systems(Update, apply_movement_tuning);
+    }
+}
+
+/// Every feel number of the player, loaded from `player.tuning.ron`
+#[derive(Asset, Reflect, Deserialize, Clone, Debug)]
+pub struct MovementTuning {
+    pub moving: StateTuning,
+    pub sliding: StateTuning,
+    pub crouched: StateTuning,
+    pub falling: StateTuning,
+    pub jumping: StateTuning,
+    pub dive: StateTuning,
+    pub glide: StateTuning,
+    pub swinging: StateTuning,
+    pub dashing: StateTuning,
+    pub swimming_surface: StateTuning,
+    pub swimming_underwater: StateTuning,
+
+    pub normal_jump: JumpTuning,
+    pub crouch_jump: JumpTuning,
+    pub dive_jump: JumpTuning,
+    pub double_jump: JumpTuning,
+    pub long_jump: LongJumpTuning,
+    pub dash: DashTuning,
+
+    /// Air actions given back on landing, 255 never runs out
+    pub air_actions: AirActions,
+
+    /// Time after leaving the ground where the player can still jump
+    pub coyote_time: f32,
+    /// Time a jump press is remembered for until a jump is possible
+    pub jump_buffer_time: f32,
+    /// Time a dive press is remembered for until a dive is possible
+    pub dive_buffer_time: f32,
+    /// Speed needed to start sliding instead of crouching
+    pub min_slide_speed: f32,
+    /// Speed under which a slide stops
+    pub stop_slide_speed: f32,
+}
+
+#[derive(Reflect, Deserialize, Clone, Copy, Debug)]
+pub struct StateTuning {
+    pub stats: MovementStats,
+    pub gravity: Gravity,
+}
+
+#[derive(Reflect, Deserialize, Clone, Copy, Debug)]
+pub struct Gravity {
+    /// Gravity while going up
+    pub up: f32,
+    /// Gravity while going down
+    pub down: f32,
+    pub terminal_velocity: f32,
+}
+
+#[derive(Reflect, Deserialize, Clone, Copy, Debug)]
+pub struct JumpTuning {
+    pub strength: f32,
+    /// How long the jump can be held for
+    pub length: f32,
+}
+
+#[derive(Reflect, Deserialize, Clone, Copy, Debug)]
+pub struct LongJumpTuning {
+    pub strength: f32,
+    /// How long the jump can be held for
+    pub length: f32,
+    /// Horizontal speed added along the slide direction
+    pub boost: f32,
+    /// Horizontal speed the boost can't go over, faster slides keep their speed
+    pub max_speed: f32,
+    /// Horizontal speed a slide needs to long jump, slower slides do a crouch jump
+    pub min_speed: f32,
+}
+
+#[derive(Reflect, Deserialize, Clone, Copy, Debug)]
+pub struct DashTuning {
+    /// Distance covered by a full dash
+    pub distance: f32,
+    /// How long the dash locks the player in the dash state
+    pub duration: f32,
+    /// Speed kept when the dash ends
+    pub exit_speed: f32,
+}
+
+impl DashTuning {
+    pub fn speed(&self) -> f32 {
+        self.distance / self.duration.max(f32::EPSILON)
+    }
+}
+
+/// The shipped tuning, built into the game so the defaults can't drift from the asset
+static SHIPPED_TUNING: LazyLock<MovementTuning> = LazyLock::new(|| {
+    ron::from_str(include_str!("../../assets/player.tuning.ron"))
+        .unwrap_or_else(|error| panic!("Invalid {MOVEMENT_TUNING_PATH}: {error}"))
+});
+
+/// Used until the asset is loaded
+impl Default for MovementTuning {
+    fn default() -> Self {
+        SHIPPED_TUNING.clone()
+    }
+}
+
+#[derive(Resource)]
+struct MovementTuningHandle(Handle<MovementTuning>);
+
+fn load_movement_tuning(mut commands: Commands, asset_server: Res<AssetServer>) {
+    commands.insert_resource(MovementTuningHandle(
+        asset_server.load(MOVEMENT_TUNING_PATH),
+    ));
+}
+
+fn apply_movement_tuning(
+    mut events: MessageReader<AssetEvent<MovementTuning>>,
+    handle: Option<Res<MovementTuningHandle>>,
+    tunings: Res<Assets<MovementTuning>>,
+    machines: Query<&mut StateMachine>,
+) {
+    let Some(handle) = handle else {
+        return;
+    };
+
+    let mut reloaded = false;
+    for event in events.read() {
+        match event {
+            AssetEvent::Added { id }
+            | AssetEvent::Modified { id }
+            | AssetEvent::LoadedWithDependencies { id } => {
+                if handle.0.id() == *id {
+                    reloaded = true;
+                }
+            }
+            AssetEvent::Unused { id: _ } | AssetEvent::Removed { id: _ } => {}
+        }
+    }
+
+    let Some(tuning) = tunings.get(&handle.0) else {
+        return;
+    };
+
+    for mut machine in machines {
+        if reloaded || machine.is_added() {
+            machine.tuning = tuning.clone();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn shipped_tuning_is_valid() {
+        let tuning = MovementTuning::default();
+
+        assert!(tuning.stop_slide_speed <= tuning.min_slide_speed);
+        assert!(tuning.long_jump.min_speed >= tuning.min_slide_speed);
+    }
+}
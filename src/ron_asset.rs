@@ -0,0 +1,59 @@
+use bevy::asset::{AssetLoader, LoadContext, io::Reader};
+use bevy::prelude::*;
+use serde::de::DeserializeOwned;
+
+use std::marker::PhantomData;
+
+/// Registers an asset type that is deserialized straight from a RON file
+pub struct RonAssetPlugin<A> {
+    extensions: &'static [&'static str],
+    _asset: PhantomData<fn() -> A>,
+}
+
+impl<A> RonAssetPlugin<A> {
+    /// Extensions go without the preceding dot, e.g. `"tuning.ron"`
+    pub fn new(extensions: &'static [&'static str]) -> Self {
+        Self {
+            extensions,
+            _asset: PhantomData,
+        }
+    }
+}
+
+impl<A: Asset + DeserializeOwned> Plugin for RonAssetPlugin<A> {
+    fn build(&self, app: &mut App) {
+        app.init_asset::<A>()
+            .register_asset_loader(RonAssetLoader::<A> {
+                extensions: self.extensions,
+                _asset: PhantomData,
+            });
+    }
+}
+
+#[derive(TypePath)]
+struct RonAssetLoader<A> {
+    extensions: &'static [&'static str],
+    _asset: PhantomData<fn() -> A>,
+}
+
+impl<A: Asset + DeserializeOwned> AssetLoader for RonAssetLoader<A> {
+    type Asset = A;
+    type Settings = ();
+    type Error = BevyError;
+
+    async fn load(
+        &self,
+        reader: &mut dyn Reader,
+        _settings: &Self::Settings,
+        _load_context: &mut LoadContext<'_>,
+    ) -> Result<Self::Asset, Self::Error> {
+        let mut bytes = Vec::new();
+        reader.read_to_end(&mut bytes).await?;
+
+        Ok(ron::de::from_bytes(&bytes)?)
+    }
+
+    fn extensions(&self) -> &[&str] {
+        self.extensions
+    }
+}